@@ -3,13 +3,179 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * The outcome of the `extern "C"` functions, details
+ * of the last failure are given by `last_error_message`
+ */
+typedef enum EmulatorStatus {
+  Ok,
+  Io,
+  MemoryOutOfBounds,
+  InvalidSaveState,
+  InvalidRom,
+  UnsupportedInterrupt,
+  InvalidArgument,
+  Disconnected,
+  /**
+   * A bug in the emulator, please open an issue
+   */
+  Panic,
+} EmulatorStatus;
+
+typedef enum Pacing {
+  Sleep,
+  BusyWait,
+  HostDriven,
+} Pacing;
+
+/**
+ * How a `TraceRecord` is rendered as a line of text
+ */
+typedef enum TraceFormat {
+  /**
+   * `0100: C3 AB 01  JMP 01ABH      A=00 BC=0000 DE=0000 HL=0000 SP=0000 F=-Z-P- CYC=0`
+   */
+  Listing,
+  /**
+   * `PC: 0100, AF: 0002, BC: 0000, DE: 0000, HL: 0000, SP: 0000, CYC: 0\t(C3 AB 01 00)`,
+   * the debug output of superzazu/8080 and of many emulators
+   * following it, to find the first divergence with `diff`
+   */
+  Registers,
+} TraceFormat;
+
+/**
+ * The CPU to emulate, see `Cpu8080::with_variant`
+ */
+typedef enum Variant {
+  I8080,
+  /**
+   * Adds RIM/SIM, the TRAP and RST 5.5/6.5/7.5 inputs, the serial
+   * pins, its own timings, and the undocumented instructions and
+   * V/K flags in place of the 8080 undocumented opcodes
+   */
+  I8085,
+} Variant;
+
+/**
+ * The interrupt inputs the 8085 adds to INTR, from the highest priority
+ */
+typedef enum InterruptPin {
+  /**
+   * Not maskable, taken on a rising edge while the level is held, RST 4.5
+   */
+  Trap,
+  /**
+   * Latched on a rising edge, RST 7.5
+   */
+  Rst75,
+  /**
+   * Level sensitive, RST 6.5
+   */
+  Rst65,
+  /**
+   * Level sensitive, RST 5.5
+   */
+  Rst55,
+} InterruptPin;
+
+/**
+ * The cabinet controls, active while pressed
+ */
+typedef enum Button {
+  Coin,
+  P1Start,
+  P2Start,
+  P1Shot,
+  P1Left,
+  P1Right,
+  P2Shot,
+  P2Left,
+  P2Right,
+  Tilt,
+} Button;
+
+/**
+ * The sounds triggered through the latches on ports 3 and 5
+ */
+typedef enum Sound {
+  /**
+   * Loops while the UFO is on screen
+   */
+  Ufo,
+  Shot,
+  PlayerDie,
+  InvaderDie,
+  ExtendedPlay,
+  Fleet1,
+  Fleet2,
+  Fleet3,
+  Fleet4,
+  UfoHit,
+} Sound;
+
+typedef enum PixelFormat {
+  /**
+   * 4 bytes per pixel: red, green, blue, alpha
+   */
+  Rgba8888,
+  /**
+   * 2 bytes per pixel in native endianness,
+   * red in the 5 most significant bits
+   */
+  Rgb565,
+} PixelFormat;
+
+/**
+ * How the monitor is mounted in the cabinet
+ */
+typedef enum Rotation {
+  Upright,
+  Clockwise,
+  /**
+   * Space Invaders: each VRAM row is a screen column, from bottom to top
+   */
+  CounterClockwise,
+} Rotation;
+
 typedef struct Cpu8080 Cpu8080;
 
+/**
+ * The whole board, driven by the host one frame at a time
+ */
+typedef struct SpaceInvaders SpaceInvaders;
+
 typedef enum Message_Tag {
+  /**
+   * RST `irq_no`, 0 to 7, others are dropped
+   */
   Interrupt,
   Suspend,
   Restart,
   Shutdown,
+  /**
+   * Change the clock rate in Hz, 0 runs unthrottled
+   */
+  SetFrequency,
+  /**
+   * Serialize the machine into `buffer`, which
+   * must stay valid until `completion` is called
+   */
+  SaveState,
+  /**
+   * Restore the machine from `buffer`, which
+   * must stay valid until `completion` is called
+   */
+  LoadState,
+  /**
+   * Any instruction placed on the bus by the interrupting
+   * device, e.g. a 3-byte CALL, unused bytes are ignored
+   */
+  InterruptInstruction,
+  /**
+   * Drive an interrupt input of the 8085, ignored by the 8080
+   */
+  SetInterruptPin,
 } Message_Tag;
 
 typedef struct Interrupt_Body {
@@ -17,10 +183,49 @@ typedef struct Interrupt_Body {
   bool allow_nested_interrupt;
 } Interrupt_Body;
 
+typedef struct SetFrequency_Body {
+  uint64_t frequency;
+} SetFrequency_Body;
+
+/**
+ * Called once a `SaveState`/`LoadState` message is handled,
+ * `size` is the number of bytes written/read, 0 on failure
+ */
+typedef void (*StateCompletion)(const void *context, uintptr_t size);
+
+typedef struct SaveState_Body {
+  uint8_t *buffer;
+  uintptr_t len;
+  StateCompletion completion;
+  const void *context;
+} SaveState_Body;
+
+typedef struct LoadState_Body {
+  const uint8_t *buffer;
+  uintptr_t len;
+  StateCompletion completion;
+  const void *context;
+} LoadState_Body;
+
+typedef struct InterruptInstruction_Body {
+  uint8_t instruction[3];
+  bool allow_nested_interrupt;
+} InterruptInstruction_Body;
+
+typedef struct SetInterruptPin_Body {
+  enum InterruptPin pin;
+  bool level;
+} SetInterruptPin_Body;
+
 typedef struct Message {
   Message_Tag tag;
   union {
     Interrupt_Body interrupt;
+    SetFrequency_Body set_frequency;
+    SaveState_Body save_state;
+    LoadState_Body load_state;
+    InterruptInstruction_Body interrupt_instruction;
+    SetInterruptPin_Body set_interrupt_pin;
   };
 } Message;
 
@@ -41,24 +246,156 @@ typedef struct IoCallbacks {
   void (*output)(const void *io_object, uint8_t port, uint8_t value);
 } IoCallbacks;
 
+/**
+ * A snapshot of all the registers and flags of the CPU,
+ * see `Cpu8080::state` and `Cpu8080::set_state`
+ */
+typedef struct CpuState {
+  uint8_t reg_a;
+  uint8_t reg_b;
+  uint8_t reg_c;
+  uint8_t reg_d;
+  uint8_t reg_e;
+  uint8_t reg_h;
+  uint8_t reg_l;
+  /**
+   * Condition codes as pushed by PUSH PSW, see `ConditionCodes::to_psw`
+   * and `ConditionCodes::to_psw_8085`
+   */
+  uint8_t flags;
+  uint16_t sp;
+  uint16_t pc;
+  bool interrupt_enabled;
+  /**
+   * HLT was executed, waiting for an interrupt
+   */
+  bool halted;
+  /**
+   * The CPU the state was taken from, which lays out `flags`,
+   * `Cpu8080::set_state` keeps the variant of the CPU
+   */
+  enum Variant variant;
+} CpuState;
+
+/**
+ * Called at the end of every slice of the run loop with the real
+ * hardware time of the slice and the time spent emulating it, both
+ * in microseconds, the app returns when the next slice is due
+ */
+typedef void (*HostPacer)(const void *context, uint64_t emulated_us, uint64_t elapsed_us);
+
+/**
+ * Called with every line of the execution trace, see `set_tracer`.
+ * The line is only valid during the call.
+ */
+typedef void (*TraceCallback)(const void *context, const char *line);
+
+/**
+ * Called from the thread running the CPU when it halts with interrupts
+ * disabled, the run loop then waits for a message such as `Restart`
+ */
+typedef void (*HaltCallback)(const void *context);
+
+/**
+ * One chip of a ROM set, loaded either from the file at
+ * `path` or, when `path` is null, from `data` of `len` bytes
+ */
+typedef struct RomEntry {
+  const char *path;
+  const uint8_t *data;
+  uintptr_t len;
+  uint16_t load_address;
+  /**
+   * Expected size in bytes, 0 to skip the check
+   */
+  uintptr_t expected_size;
+  uint32_t expected_crc32;
+  bool check_crc32;
+} RomEntry;
+
+/**
+ * Where the 1bpp picture lives in RAM: `rows` of `stride` bytes
+ * from `offset`, 8 pixels per byte with the least significant bit first
+ */
+typedef struct VideoLayout {
+  uintptr_t offset;
+  uintptr_t stride;
+  uintptr_t rows;
+  enum Rotation rotation;
+} VideoLayout;
+
+/**
+ * Lit pixels inside the rectangle take `color` (0xRRGGBB) instead
+ * of white, like the cellophane stripes glued on the monitor
+ */
+typedef struct ColorBand {
+  uintptr_t x;
+  uintptr_t y;
+  uintptr_t width;
+  uintptr_t height;
+  uint32_t color;
+} ColorBand;
+
+/**
+ * A sound latch bit has changed, `playing` is
+ * true when it starts and false when it stops
+ */
+typedef struct SoundEvent {
+  enum Sound sound;
+  bool playing;
+} SoundEvent;
+
+/**
+ * Called for every sound change since the last call,
+ * see `space_invaders_take_sound_events`
+ */
+typedef void (*SoundCallback)(const void *context, struct SoundEvent event);
+
 /**
  * # Safety
  * This function should be called with valid rom path
- * and the RAM will be allocated on the fly
+ * and the RAM will be allocated on the fly.
+ * Both pointers are null if the CPU cannot be created.
  */
 struct CpuSender new_cpu_instance(const char *rom_path,
                                   uintptr_t ram_size,
                                   struct IoCallbacks callbacks,
                                   const void *io_object);
 
+/**
+ * # Safety
+ * `rom` needs to be valid for `rom_len` bytes, it is copied
+ * so the app can release it once this function returns.
+ * Both pointers are null if the CPU cannot be created.
+ */
+struct CpuSender new_cpu_instance_from_bytes(const uint8_t *rom,
+                                             uintptr_t rom_len,
+                                             uintptr_t ram_size,
+                                             struct IoCallbacks callbacks,
+                                             const void *io_object);
+
+/**
+ * # Safety
+ * `entries` needs to point to `count` valid entries, the chips
+ * are assembled into one ROM image, see `load_rom_set`.
+ * Both pointers are null if the CPU cannot be created.
+ */
+struct CpuSender new_cpu_instance_from_rom_set(const struct RomEntry *entries,
+                                               uintptr_t count,
+                                               uintptr_t ram_size,
+                                               struct IoCallbacks callbacks,
+                                               const void *io_object);
+
 /**
  * # Safety
  * This function should be safe to start a run loop.
  * Send a `Shutdown` message can break the loop, so
  * that the CPU and the Sender will be dropped, this is
  * the only way to release the resources to the system.
+ * They are dropped as well when the loop fails, the
+ * error is reported on the thread calling `run`.
  */
-void run(struct Cpu8080 *cpu, void *sender);
+enum EmulatorStatus run(struct Cpu8080 *cpu, void *sender);
 
 /**
  * # Safety
@@ -66,9 +403,228 @@ void run(struct Cpu8080 *cpu, void *sender);
  */
 const uint8_t *get_ram(const struct Cpu8080 *cpu);
 
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call
+ * this while the `run` loop owns the CPU.
+ */
+enum EmulatorStatus get_cpu_state(const struct Cpu8080 *cpu, struct CpuState *state);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call
+ * this while the `run` loop owns the CPU.
+ */
+enum EmulatorStatus set_cpu_state(struct Cpu8080 *cpu, struct CpuState state);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call this while the
+ * `run` loop owns the CPU, send `SetFrequency` instead.
+ * `pacer` and `context` are only used with `HostDriven` pacing.
+ */
+enum EmulatorStatus set_clock(struct Cpu8080 *cpu,
+                              uint64_t frequency,
+                              uint64_t slice_cycles,
+                              enum Pacing pacing,
+                              HostPacer pacer,
+                              const void *context);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call
+ * this while the `run` loop owns the CPU.
+ */
+enum EmulatorStatus set_variant(struct Cpu8080 *cpu, enum Variant variant);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call this while the
+ * `run` loop owns the CPU, send `SetInterruptPin` instead.
+ */
+enum EmulatorStatus set_interrupt_pin(struct Cpu8080 *cpu, enum InterruptPin pin, bool level);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call
+ * this while the `run` loop owns the CPU.
+ */
+enum EmulatorStatus set_sid(struct Cpu8080 *cpu, bool level);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call this while
+ * the `run` loop owns the CPU. False if `cpu` is null.
+ */
+bool get_sod(const struct Cpu8080 *cpu);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call this while the
+ * `run` loop owns the CPU. `callback` receives a line in `format`
+ * before every instruction, a null `callback` stops tracing.
+ */
+enum EmulatorStatus set_tracer(struct Cpu8080 *cpu,
+                               enum TraceFormat format,
+                               TraceCallback callback,
+                               const void *context);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call this while
+ * the `run` loop owns the CPU. A null `callback` removes it.
+ */
+enum EmulatorStatus set_halt_callback(struct Cpu8080 *cpu,
+                                      HaltCallback callback,
+                                      const void *context);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call this while
+ * the `run` loop owns the CPU. RST `irq_no` fires once after
+ * `delay_cycles`, then every `period_cycles` unless it is 0.
+ * The id to cancel the event is set to `id` if not null.
+ */
+enum EmulatorStatus schedule_interrupt(struct Cpu8080 *cpu,
+                                       uint64_t delay_cycles,
+                                       uint64_t period_cycles,
+                                       uint8_t irq_no,
+                                       bool allow_nested_interrupt,
+                                       uint64_t *id);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, do not call
+ * this while the `run` loop owns the CPU.
+ */
+enum EmulatorStatus cancel_event(struct Cpu8080 *cpu, uint64_t id);
+
+/**
+ * # Safety
+ * The CPU instance needs to be valid, 0 if it is null.
+ */
+uintptr_t save_state_size(const struct Cpu8080 *cpu);
+
+/**
+ * # Safety
+ * `buffer` needs to be valid for `len` bytes, do not call this
+ * while the `run` loop owns the CPU, send `SaveState` instead.
+ * The number of bytes written is set to `written`.
+ */
+enum EmulatorStatus save_state(const struct Cpu8080 *cpu,
+                               uint8_t *buffer,
+                               uintptr_t len,
+                               uintptr_t *written);
+
+/**
+ * # Safety
+ * `buffer` needs to be valid for `len` bytes, do not call this
+ * while the `run` loop owns the CPU, send `LoadState` instead.
+ */
+enum EmulatorStatus load_state(struct Cpu8080 *cpu, const uint8_t *buffer, uintptr_t len);
+
 /**
  * # Safety
  * Sender needs to be present(not dropped) for
  * sending the messages to the CPU instance.
  */
-void send_message(const void *sender, struct Message message);
+enum EmulatorStatus send_message(const void *sender, struct Message message);
+
+/**
+ * # Safety
+ * `ram` needs to be valid for `ram_len` bytes, e.g. from `get_ram`,
+ * `overlay` for `overlay_len` bands (null if none), and `out` for
+ * `out_len` bytes, at least `frame_buffer_size`. Do not call this
+ * while the `run` loop writes the RAM without synchronization.
+ */
+enum EmulatorStatus decode_frame_into(const uint8_t *ram,
+                                      uintptr_t ram_len,
+                                      struct VideoLayout layout,
+                                      const struct ColorBand *overlay,
+                                      uintptr_t overlay_len,
+                                      enum PixelFormat format,
+                                      uint8_t *out,
+                                      uintptr_t out_len);
+
+/**
+ * Bytes needed by `decode_frame_into`
+ */
+uintptr_t frame_buffer_size(struct VideoLayout layout, enum PixelFormat format);
+
+/**
+ * The layout of the video RAM returned by `get_ram` for Space
+ * Invaders, pass 0 as `offset` for `space_invaders_framebuffer`
+ */
+struct VideoLayout space_invaders_video_layout(uintptr_t offset);
+
+/**
+ * The cellophane bands of the Space Invaders cabinet,
+ * `count` is set to the number of bands
+ *
+ * # Safety
+ * `count` needs to be valid
+ */
+const struct ColorBand *space_invaders_overlay(uintptr_t *count);
+
+/**
+ * # Safety
+ * `rom` needs to be valid for `rom_len` bytes, up to 8 KiB, it
+ * is copied so the app can release it once this function returns.
+ * Null if the machine cannot be created, release it with
+ * `space_invaders_free`.
+ */
+struct SpaceInvaders *space_invaders_new(const uint8_t *rom, uintptr_t rom_len);
+
+/**
+ * # Safety
+ * The machine needs to come from `space_invaders_new`,
+ * it must not be used anymore after this call.
+ */
+void space_invaders_free(struct SpaceInvaders *machine);
+
+/**
+ * # Safety
+ * The machine needs to be valid. Emulates 1/60 second,
+ * call it on every vsync of the display.
+ */
+enum EmulatorStatus space_invaders_run_frame(struct SpaceInvaders *machine);
+
+/**
+ * # Safety
+ * The machine needs to be valid.
+ */
+enum EmulatorStatus space_invaders_set_button(struct SpaceInvaders *machine,
+                                              enum Button button,
+                                              bool pressed);
+
+/**
+ * # Safety
+ * The machine needs to be valid.
+ */
+enum EmulatorStatus space_invaders_set_dip_switches(struct SpaceInvaders *machine,
+                                                    uint8_t dip_switches);
+
+/**
+ * # Safety
+ * The machine needs to be valid, the `callback` is
+ * called with every sound change, in order.
+ */
+enum EmulatorStatus space_invaders_take_sound_events(struct SpaceInvaders *machine,
+                                                     SoundCallback callback,
+                                                     const void *context);
+
+/**
+ * # Safety
+ * The machine needs to be valid, null if it is null.
+ * The framebuffer is `space_invaders_framebuffer_size` bytes,
+ * see `SpaceInvaders::framebuffer` for the layout.
+ */
+const uint8_t *space_invaders_framebuffer(const struct SpaceInvaders *machine);
+
+uintptr_t space_invaders_framebuffer_size(void);
+
+/**
+ * The message of the last error which happened on the calling thread,
+ * valid until the next error on that thread, null if none happened.
+ */
+const char *last_error_message(void);
@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+
+use crate::{disassemble, instruction_length, AsmError, Operand, Result, Syntax, Variant};
+
+/// What an operand of an instruction has to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Register(&'static str),
+    Byte,
+    Word,
+}
+
+const REGISTERS: [&str; 10] = ["B", "C", "D", "E", "H", "L", "M", "A", "SP", "PSW"];
+
+const DIRECTIVES: [&str; 7] = ["ORG", "DB", "DW", "DS", "EQU", "END", "RST"];
+
+/// Documented opcodes by mnemonic, built from the disassembler
+/// so that both always agree on the encoding
+fn opcode_table() -> HashMap<&'static str, Vec<(u8, Vec<Shape>)>> {
+    let mut table: HashMap<_, Vec<_>> = HashMap::new();
+    for opcode in 0..=0xff {
+        let instruction = disassemble(&[opcode, 0, 0], Syntax::Intel, Variant::I8080)
+            .expect("3 bytes are enough");
+        if instruction.undocumented || instruction.mnemonic == "RST" {
+            continue;
+        }
+        let shapes = instruction
+            .operands
+            .iter()
+            .map(|operand| match operand {
+                Operand::Register(name) => Shape::Register(name),
+                Operand::Byte(_) | Operand::Port(_) => Shape::Byte,
+                _ => Shape::Word,
+            })
+            .collect();
+        table
+            .entry(instruction.mnemonic)
+            .or_default()
+            .push((opcode, shapes));
+    }
+    table
+}
+
+fn is_register(operand: &str) -> bool {
+    REGISTERS
+        .iter()
+        .any(|register| register.eq_ignore_ascii_case(operand))
+}
+
+enum Symbol {
+    Value(u16),
+    /// An EQU, evaluated when needed as it may refer to later labels
+    Expression {
+        text: String,
+        here: u16,
+    },
+}
+
+type Symbols = HashMap<String, Symbol>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Text(Vec<u8>),
+    Name(String),
+    Here,
+    Operator(String),
+    Open,
+    Close,
+}
+
+const OPERATOR_WORDS: [&str; 9] = [
+    "MOD", "SHL", "SHR", "AND", "OR", "XOR", "NOT", "HIGH", "LOW",
+];
+
+fn parse_number(digits: &str) -> Option<i64> {
+    let digits = digits.to_ascii_uppercase();
+    let (digits, radix) = match digits.as_bytes().last()? {
+        b'H' => (&digits[..digits.len() - 1], 16),
+        b'B' => (&digits[..digits.len() - 1], 2),
+        b'O' | b'Q' => (&digits[..digits.len() - 1], 8),
+        b'D' => (&digits[..digits.len() - 1], 10),
+        _ => (&digits[..], 10),
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Returns the text between quotes and the rest, `''` is a quote
+fn parse_string(text: &str) -> Option<(Vec<u8>, &str)> {
+    let quote = text.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let mut bytes = vec![];
+    let mut rest = &text[1..];
+    loop {
+        let end = rest.find(quote)?;
+        bytes.extend_from_slice(&rest.as_bytes()[..end]);
+        rest = &rest[end + 1..];
+        match rest.strip_prefix(quote) {
+            Some(after) => {
+                bytes.push(quote as u8);
+                rest = after
+            }
+            None => return Some((bytes, rest)),
+        }
+    }
+}
+
+fn tokenize(mut text: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    loop {
+        text = text.trim_start();
+        let Some(c) = text.chars().next() else {
+            return Ok(tokens);
+        };
+        let word_end = |text: &str| {
+            text.find(|c: char| !(c.is_ascii_alphanumeric() || "_?@.".contains(c)))
+                .unwrap_or(text.len())
+        };
+        let len = match c {
+            '\'' | '"' => {
+                let (bytes, rest) =
+                    parse_string(text).ok_or_else(|| format!("unterminated string {text}"))?;
+                tokens.push(Token::Text(bytes));
+                text.len() - rest.len()
+            }
+            '0'..='9' => {
+                let end = word_end(text);
+                let number = parse_number(&text[..end])
+                    .ok_or_else(|| format!("invalid number {}", &text[..end]))?;
+                tokens.push(Token::Number(number));
+                end
+            }
+            '$' => {
+                tokens.push(Token::Here);
+                1
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                1
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                1
+            }
+            '+' | '-' | '*' | '/' | '&' | '|' | '^' | '%' => {
+                tokens.push(Token::Operator(c.to_string()));
+                1
+            }
+            _ if c.is_ascii_alphabetic() || "_?@.".contains(c) => {
+                let end = word_end(text);
+                let word = text[..end].to_ascii_uppercase();
+                if OPERATOR_WORDS.contains(&word.as_str()) {
+                    tokens.push(Token::Operator(word))
+                } else {
+                    tokens.push(Token::Name(word))
+                }
+                end
+            }
+            _ => return Err(format!("unexpected character {c}")),
+        };
+        text = &text[len..];
+    }
+}
+
+/// Recursive descent over the tokens, from the lowest precedence:
+/// OR/XOR, AND, +/-, * / MOD SHL SHR, then unary operators
+struct Evaluator<'a> {
+    symbols: &'a Symbols,
+    /// None past the end of the address space
+    here: Option<u16>,
+    depth: usize,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+type Value = std::result::Result<i64, String>;
+
+impl Evaluator<'_> {
+    fn evaluate(symbols: &Symbols, here: Option<u16>, depth: usize, text: &str) -> Value {
+        if depth > 32 {
+            return Err("circular definition".to_string());
+        }
+        let mut evaluator = Evaluator {
+            symbols,
+            here,
+            depth,
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let value = evaluator.binary(0)?;
+        match evaluator.tokens.get(evaluator.position) {
+            None => Ok(value),
+            Some(token) => Err(format!("unexpected {token:?} in {text}")),
+        }
+    }
+
+    fn next_operator(&self, operators: &[&str]) -> Option<String> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) if operators.contains(&operator.as_str()) => {
+                Some(operator.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Value {
+        const LEVELS: [&[&str]; 4] = [
+            &["OR", "XOR", "|", "^"],
+            &["AND", "&"],
+            &["+", "-"],
+            &["*", "/", "MOD", "%", "SHL", "SHR"],
+        ];
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut value = self.binary(level + 1)?;
+        while let Some(operator) = self.next_operator(operators) {
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            let shift = u32::try_from(rhs).ok();
+            value = match operator.as_str() {
+                "OR" | "|" => Some(value | rhs),
+                "XOR" | "^" => Some(value ^ rhs),
+                "AND" | "&" => Some(value & rhs),
+                "+" => value.checked_add(rhs),
+                "-" => value.checked_sub(rhs),
+                "*" => value.checked_mul(rhs),
+                "SHL" => shift.and_then(|shift| value.checked_shl(shift)),
+                "SHR" => shift.and_then(|shift| value.checked_shr(shift)),
+                _ if rhs == 0 => return Err("division by zero".to_string()),
+                "/" => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            }
+            .ok_or_else(|| format!("{value} {operator} {rhs} overflows"))?
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Value {
+        if let Some(operator) = self.next_operator(&["-", "+", "NOT", "HIGH", "LOW"]) {
+            self.position += 1;
+            let value = self.unary()?;
+            return Ok(match operator.as_str() {
+                "-" => value
+                    .checked_neg()
+                    .ok_or_else(|| format!("-{value} overflows"))?,
+                "NOT" => !value,
+                "HIGH" => value >> 8 & 0xff,
+                "LOW" => value & 0xff,
+                _ => value,
+            });
+        }
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Text(bytes)) if (1..=2).contains(&bytes.len()) => Ok(bytes
+                .iter()
+                .fold(0, |value, &byte| value << 8 | byte as i64)),
+            Some(Token::Here) => self
+                .here
+                .map(i64::from)
+                .ok_or_else(|| "$ is past FFFFH".to_string()),
+            Some(Token::Name(name)) => match self.symbols.get(&name) {
+                Some(Symbol::Value(value)) => Ok(*value as i64),
+                Some(Symbol::Expression { text, here }) => {
+                    Self::evaluate(self.symbols, Some(*here), self.depth + 1, text)
+                }
+                None => Err(format!("undefined symbol {name}")),
+            },
+            Some(Token::Open) => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {token:?}")),
+            None => Err("missing operand".to_string()),
+        }
+    }
+}
+
+/// Strip the comment, a `;` inside quotes does not start one
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            (';', None) => return &line[..i],
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Split at the commas which are not inside quotes
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            (',', None) => {
+                operands.push(text[start..i].trim().to_string());
+                start = i + 1
+            }
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => (),
+        }
+    }
+    let last = text[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last.to_string())
+    }
+    operands
+}
+
+/// A DB item which is nothing but a string
+fn string_item(item: &str) -> Option<Vec<u8>> {
+    match parse_string(item)? {
+        (bytes, "") => Some(bytes),
+        _ => None,
+    }
+}
+
+enum Statement {
+    Instruction {
+        opcode: u8,
+        operands: Vec<(Shape, String)>,
+    },
+    Rst(String),
+    Db(Vec<String>),
+    Dw(Vec<String>),
+}
+
+struct Located {
+    line: usize,
+    addr: u16,
+    statement: Statement,
+}
+
+/// Assemble Intel 8080 source into an image of the address space
+/// from 0000H up to the last byte emitted, gaps are filled with 0.
+///
+/// Supported: labels (the colon is optional at the start of a line),
+/// all documented mnemonics, `ORG`, `DB`, `DW`, `DS`, `EQU`, `END`,
+/// and expressions made of numbers (`10`, `0AH`, `101B`, `17O`),
+/// characters, symbols, `$`, `+ - * / MOD SHL SHR AND OR XOR NOT
+/// HIGH LOW` and parentheses.
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let table = opcode_table();
+    let mut symbols = Symbols::new();
+    let mut statements = vec![];
+    // up to 10000H once the last byte has been placed at FFFFH
+    let mut addr: u32 = 0;
+
+    // first pass: the address of every label and statement
+    for (number, line) in source.lines().enumerate() {
+        let line_number = number + 1;
+        let error = |message: String| AsmError {
+            line: line_number,
+            message,
+        };
+        let code = strip_comment(line).trim_end();
+        let mut rest = code.trim_start();
+        let mut label = None;
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == ':')
+            .unwrap_or(rest.len());
+        let first = &rest[..end];
+        let after = &rest[end..];
+        let at_column_0 = !code.starts_with(char::is_whitespace);
+        let is_keyword = |word: &str| {
+            let word = word.to_ascii_uppercase();
+            table.contains_key(word.as_str()) || DIRECTIVES.contains(&word.as_str())
+        };
+        if !first.is_empty() && (after.starts_with(':') || at_column_0 && !is_keyword(first)) {
+            label = Some(first.to_ascii_uppercase());
+            rest = after.strip_prefix(':').unwrap_or(after).trim_start();
+        }
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(end) => (&rest[..end], rest[end..].trim()),
+            None => (rest, ""),
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let operands = split_operands(operands);
+        let here = u16::try_from(addr).ok();
+        let exceeded = || error("the 64 KiB address space is exceeded".to_string());
+
+        if mnemonic == "EQU" {
+            let name = label.ok_or_else(|| error("EQU without a name".to_string()))?;
+            let [text] = operands.as_slice() else {
+                return Err(error("EQU takes one expression".to_string()).into());
+            };
+            let text = text.clone();
+            let here = here.ok_or_else(exceeded)?;
+            if symbols
+                .insert(name.clone(), Symbol::Expression { text, here })
+                .is_some()
+            {
+                return Err(error(format!("{name} is defined twice")).into());
+            }
+            continue;
+        }
+        if let Some(name) = label {
+            let value = here.ok_or_else(exceeded)?;
+            if symbols.insert(name.clone(), Symbol::Value(value)).is_some() {
+                return Err(error(format!("{name} is defined twice")).into());
+            }
+        }
+        // ORG and DS shape the layout, they cannot refer to later symbols
+        let evaluate_now = |text: &str| {
+            Evaluator::evaluate(&symbols, here, 0, text)
+                .and_then(|value| {
+                    u16::try_from(value).map_err(|_| format!("{value} is out of range"))
+                })
+                .map_err(error)
+        };
+        let single = || match operands.as_slice() {
+            [operand] => Ok(operand.as_str()),
+            _ => Err(error(format!("{mnemonic} takes one operand"))),
+        };
+        let (statement, size) = match mnemonic.as_str() {
+            "" => continue,
+            "END" => break,
+            "ORG" => {
+                addr = evaluate_now(single()?)?.into();
+                continue;
+            }
+            "DS" => {
+                addr += u32::from(evaluate_now(single()?)?);
+                if addr > 0x10000 {
+                    return Err(exceeded().into());
+                }
+                continue;
+            }
+            "DB" => {
+                let size = operands
+                    .iter()
+                    .map(|item| string_item(item).map_or(1, |bytes| bytes.len()))
+                    .sum::<usize>();
+                (Statement::Db(operands), size)
+            }
+            "DW" => {
+                let size = operands.len() * 2;
+                (Statement::Dw(operands), size)
+            }
+            "RST" => (Statement::Rst(single()?.to_string()), 1),
+            _ => {
+                let candidates = table
+                    .get(mnemonic.as_str())
+                    .ok_or_else(|| error(format!("unknown instruction {mnemonic}")))?;
+                let (opcode, shapes) = candidates
+                    .iter()
+                    .find(|(_, shapes)| {
+                        shapes.len() == operands.len()
+                            && shapes
+                                .iter()
+                                .zip(&operands)
+                                .all(|(shape, operand)| match shape {
+                                    Shape::Register(name) => name.eq_ignore_ascii_case(operand),
+                                    _ => !is_register(operand),
+                                })
+                    })
+                    .ok_or_else(|| error(format!("invalid operands for {mnemonic}")))?;
+                let operands = shapes.iter().copied().zip(operands).collect();
+                let statement = Statement::Instruction {
+                    opcode: *opcode,
+                    operands,
+                };
+                (
+                    statement,
+                    instruction_length(*opcode, Variant::I8080) as usize,
+                )
+            }
+        };
+        let start = here.ok_or_else(exceeded)?;
+        addr += size as u32;
+        if addr > 0x10000 {
+            return Err(exceeded().into());
+        }
+        statements.push(Located {
+            line: line_number,
+            addr: start,
+            statement,
+        });
+    }
+
+    // second pass: every symbol is known, emit the bytes
+    let mut image = vec![];
+    for Located {
+        line,
+        addr,
+        statement,
+    } in statements
+    {
+        let error = |message: String| AsmError { line, message };
+        let evaluate = |text: &str, min: i64, max: i64| {
+            Evaluator::evaluate(&symbols, Some(addr), 0, text)
+                .and_then(|value| match (min..=max).contains(&value) {
+                    true => Ok(value),
+                    false => Err(format!("{text} = {value} is out of range")),
+                })
+                .map_err(error)
+        };
+        let byte = |text: &str| Ok::<_, AsmError>(evaluate(text, -0x80, 0xff)? as u8);
+        let word =
+            |text: &str| Ok::<_, AsmError>((evaluate(text, -0x8000, 0xffff)? as u16).to_le_bytes());
+        let mut bytes = vec![];
+        match statement {
+            Statement::Instruction { opcode, operands } => {
+                bytes.push(opcode);
+                for (shape, operand) in operands {
+                    match shape {
+                        Shape::Register(_) => (),
+                        Shape::Byte => bytes.push(byte(&operand)?),
+                        Shape::Word => bytes.extend(word(&operand)?),
+                    }
+                }
+            }
+            Statement::Rst(vector) => bytes.push(0xc7 | (evaluate(&vector, 0, 7)? as u8) << 3),
+            Statement::Db(items) => {
+                for item in items {
+                    match string_item(&item) {
+                        Some(text) => bytes.extend(text),
+                        None => bytes.push(byte(&item)?),
+                    }
+                }
+            }
+            Statement::Dw(items) => {
+                for item in items {
+                    bytes.extend(word(&item)?)
+                }
+            }
+        }
+        let start = addr as usize;
+        let end = start + bytes.len();
+        if image.len() < end {
+            image.resize(end, 0)
+        }
+        image[start..end].copy_from_slice(&bytes);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebuild_cpudiag() {
+        let source = include_str!("../diagnosis_program/cpudiag.asm");
+        let binary = include_bytes!("../diagnosis_program/cpudiag");
+        assert_eq!(assemble(source).unwrap(), binary);
+    }
+
+    #[test]
+    fn directives_and_expressions() {
+        let source = "
+COUNT   EQU     END - START     ; forward references are fine in EQU
+        ORG     10H
+START:  MVI     A,COUNT
+        LXI     H,'AB'
+        mov     m,a
+loop    JNZ     LOOP
+        RST     COUNT SHR 2
+        DB      'It''s', -1, LOW 1234H, ';'
+        DW      $, HIGH START + 1
+        DS      2
+END:    DB      10 MOD 3, 101B, 17O
+";
+        let image = assemble(source).unwrap();
+        assert_eq!(
+            image[0x10..],
+            [
+                0x3e, 0x17, 0x21, 0x42, 0x41, 0x77, 0xc2, 0x16, 0x00, 0xef, b'I', b't', b'\'',
+                b's', 0xff, 0x34, b';', 0x21, 0x00, 0x01, 0x00, 0, 0, 0x01, 0x05, 0x0f
+            ]
+        );
+        assert!(image[..0x10].iter().all(|&byte| byte == 0));
+
+        let error = |source| match assemble(source) {
+            Err(crate::EmulatorErrors::Asm(error)) => (error.line, error.message),
+            _ => panic!("{source} should not assemble"),
+        };
+        assert_eq!(
+            error("NOP\n MVI A,256"),
+            (2, "256 = 256 is out of range".to_string())
+        );
+        assert_eq!(
+            error(" MOV A,SP"),
+            (1, "invalid operands for MOV".to_string())
+        );
+        assert_eq!(
+            error(" JMP NOWHERE"),
+            (1, "undefined symbol NOWHERE".to_string())
+        );
+        assert_eq!(
+            error("A EQU B\nB EQU A\n DB A"),
+            (3, "circular definition".to_string())
+        );
+        assert_eq!(
+            error(" DW 8000000000000000H*2"),
+            (1, "invalid number 8000000000000000H".to_string())
+        );
+        assert_eq!(
+            error(" DW 4000000000000000H*2"),
+            (1, "4611686018427387904 * 2 overflows".to_string())
+        );
+        assert_eq!(
+            error(" DB 1 SHL 200"),
+            (1, "1 SHL 200 overflows".to_string())
+        );
+        assert_eq!(
+            error(" ORG 0FFFEH\n NOP\n NOP\n NOP"),
+            (4, "the 64 KiB address space is exceeded".to_string())
+        );
+        assert_eq!(
+            error(" ORG 0FFF0H\n DS 20H"),
+            (2, "the 64 KiB address space is exceeded".to_string())
+        );
+        assert_eq!(assemble(" ORG 0FFFFH\n RST 7").unwrap().len(), 0x10000);
+    }
+}
@@ -1,19 +1,29 @@
 use std::{
+    env,
     fs::File,
-    io::{BufReader, Read},
+    io::{self, BufReader, Read},
     path::Path,
 };
 
-use i8080emulator::{Cpu8080, Result};
+use i8080emulator::{load_com, Bdos, Cpu8080, Message, Result, TraceFormat, TraceWriter};
 
 fn main() -> Result<()> {
     let cpudiag_prog = Path::new(env!("CARGO_MANIFEST_DIR")).join("diagnosis_program/cpudiag");
 
     println!("executing CPU diagnosis...");
     let bytes = BufReader::new(File::open(cpudiag_prog)?).bytes();
-    let rom = bytes.collect::<std::result::Result<Vec<u8>, std::io::Error>>()?;
-    let ram = vec![0; 0x200];
-    let mut cpu = Cpu8080::cpudiag_new(rom, ram);
+    let image = bytes.collect::<std::result::Result<Vec<u8>, std::io::Error>>()?;
+    // the image is assembled with ORG 0100H, the leading bytes are just padding
+    let (mut cpu, sender) = Cpu8080::with_memory(load_com(&image[0x100..])?, ());
+    cpu.enable_cpm(Bdos::new(io::stdout()));
+    // a trace to diff against another emulator's
+    if env::args().any(|arg| arg == "--trace") {
+        cpu.set_tracer(TraceWriter::new(io::stderr(), TraceFormat::Registers))
+    }
+    // the run loop starts suspended
+    sender.send(Message::Suspend).unwrap();
     cpu.run()?;
+    println!();
+    println!("RE-ENTRY TO CP/M WARM BOOT, exiting...");
     Ok(())
 }
@@ -0,0 +1,381 @@
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    io::{self, BufRead, Write},
+    process,
+};
+
+use i8080emulator::{
+    disassemble_at, instruction_length, is_call, load_com, Bdos, Cpu8080, MemoryBus, Result,
+    RomRam, Syntax, TraceBuffer, TraceFormat,
+};
+
+const USAGE: &str = "usage: i8080dbg [--com] <image>
+  --com  load a CP/M program at 0100H with the console BDOS,
+         otherwise the image is a ROM at 0000H followed by RAM";
+
+const HELP: &str = "commands, numbers are hexadecimal:
+  s [n]            step n instructions, 1 by default
+  n                step over CALL and RST
+  c                continue until a breakpoint or a watchpoint
+  b [addr]         set a breakpoint, list them without address
+  db addr          delete a breakpoint
+  w [addr]         set a watchpoint on writes, list them without address
+  dw addr          delete a watchpoint
+  r                show registers and flags
+  x addr [len]     dump memory
+  l [addr] [n]     list n instructions from addr, PC by default
+  t [n]            show the last n executed instructions, 16 by default
+  set reg value    set a, b, c, d, e, h, l, bc, de, hl, sp, pc or psw
+  poke addr b...   write bytes to memory
+  h                show this help
+  q                quit
+an empty line repeats the last command";
+
+/// Records the writes to watched addresses
+struct Watched<M> {
+    inner: M,
+    watchpoints: BTreeSet<u16>,
+    hits: Vec<(u16, u8)>,
+}
+
+impl<M: MemoryBus> MemoryBus for Watched<M> {
+    fn read(&self, addr: u16) -> Result<u8> {
+        self.inner.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) -> Result<()> {
+        if self.watchpoints.contains(&addr) {
+            self.hits.push((addr, value))
+        }
+        self.inner.write(addr, value)
+    }
+
+    fn ram(&self) -> &[u8] {
+        self.inner.ram()
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        self.inner.ram_mut()
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.inner.rom()
+    }
+}
+
+type Cpu = Cpu8080<Watched<RomRam>, ()>;
+
+struct Debugger {
+    cpu: Cpu,
+    breakpoints: BTreeSet<u16>,
+    history: TraceBuffer,
+}
+
+fn parse_number(arg: &str) -> Option<u16> {
+    let digits = arg
+        .strip_prefix("0x")
+        .or_else(|| arg.strip_suffix(['h', 'H']))
+        .unwrap_or(arg);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+impl Debugger {
+    fn step(&mut self) -> Result<()> {
+        self.cpu.step()?;
+        Ok(())
+    }
+
+    /// Why the execution should stop after the last step, if it should
+    fn stop_reason(&mut self) -> Option<String> {
+        let pc = self.cpu.state().pc;
+        let hits = std::mem::take(&mut self.cpu.memory_mut().hits);
+        if let Some(&(addr, value)) = hits.last() {
+            Some(format!("watchpoint {addr:04X} written with {value:02X}"))
+        } else if self.breakpoints.contains(&pc) {
+            Some(format!("breakpoint {pc:04X}"))
+        } else if self.cpu.has_exited() {
+            Some("program exited".to_string())
+        } else if self.cpu.is_halted() {
+            // no device interrupts the CPU in the debugger
+            Some("halted".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Step until something stops the execution, or until `pc` is reached
+    fn run(&mut self, until: Option<u16>) -> Result<()> {
+        loop {
+            self.step()?;
+            if let Some(reason) = self.stop_reason() {
+                println!("{reason}");
+                return Ok(());
+            }
+            if Some(self.cpu.state().pc) == until {
+                return Ok(());
+            }
+        }
+    }
+
+    fn step_over(&mut self) -> Result<()> {
+        let pc = self.cpu.state().pc;
+        let opcode = self.cpu.memory().read(pc)?;
+        let variant = self.cpu.variant();
+        if is_call(opcode, variant) {
+            self.run(Some(pc.wrapping_add(instruction_length(opcode, variant))))
+        } else {
+            self.step()?;
+            if let Some(reason) = self.stop_reason() {
+                println!("{reason}")
+            }
+            Ok(())
+        }
+    }
+
+    fn show_registers(&self) {
+        let state = self.cpu.state();
+        println!(
+            "A={:02X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} INTE={} HALTED={}",
+            state.reg_a,
+            state.bc(),
+            state.de(),
+            state.hl(),
+            state.sp,
+            state.pc,
+            state.interrupt_enabled as u8,
+            state.halted as u8
+        );
+        print!("{}", state.condition_codes());
+    }
+
+    fn dump(&self, addr: u16, len: u16) {
+        for row in (0..len).step_by(16) {
+            let start = addr.wrapping_add(row);
+            let bytes = (0..(len - row).min(16))
+                .map(
+                    |offset| match self.cpu.memory().read(start.wrapping_add(offset)) {
+                        Ok(byte) => format!("{byte:02X}"),
+                        Err(_) => "??".to_string(),
+                    },
+                )
+                .collect::<Vec<_>>();
+            println!("{start:04X}: {}", bytes.join(" "));
+        }
+    }
+
+    fn list(&self, mut addr: u16, count: usize) {
+        let pc = self.cpu.state().pc;
+        for _ in 0..count {
+            let memory = self.cpu.memory();
+            let Ok(instruction) = disassemble_at(memory, addr, Syntax::Intel, self.cpu.variant())
+            else {
+                return;
+            };
+            let bytes = (0..instruction.len)
+                .filter_map(|i| self.cpu.memory().read(addr.wrapping_add(i)).ok())
+                .map(|byte| format!("{byte:02X}"))
+                .collect::<Vec<_>>();
+            let marker = if addr == pc { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&addr) {
+                "*"
+            } else {
+                " "
+            };
+            println!(
+                "{marker}{breakpoint}{addr:04X}: {:<9} {instruction}",
+                bytes.join(" ")
+            );
+            addr = addr.wrapping_add(instruction.len);
+        }
+    }
+
+    fn show_history(&self, count: usize) {
+        let records = self.history.records();
+        for record in &records[records.len().saturating_sub(count)..] {
+            println!("{}", record.format(TraceFormat::Listing))
+        }
+    }
+
+    fn set_register(&mut self, register: &str, value: u16) -> bool {
+        let mut state = self.cpu.state();
+        match register {
+            "a" => state.reg_a = value as u8,
+            "b" => state.reg_b = value as u8,
+            "c" => state.reg_c = value as u8,
+            "d" => state.reg_d = value as u8,
+            "e" => state.reg_e = value as u8,
+            "h" => state.reg_h = value as u8,
+            "l" => state.reg_l = value as u8,
+            "bc" => state.set_bc(value),
+            "de" => state.set_de(value),
+            "hl" => state.set_hl(value),
+            "sp" => state.sp = value,
+            "pc" => state.pc = value,
+            "psw" => state.flags = value as u8,
+            _ => return false,
+        }
+        self.cpu.set_state(&state);
+        true
+    }
+
+    /// Returns false to quit
+    fn execute(&mut self, command: &str, args: &[u16]) -> Result<bool> {
+        let pc = self.cpu.state().pc;
+        match (command, args) {
+            ("s", []) => self.step_n(1)?,
+            ("s", [count]) => self.step_n(*count)?,
+            ("n", []) => self.step_over()?,
+            ("c", []) => self.run(None)?,
+            ("b", []) => self
+                .breakpoints
+                .iter()
+                .for_each(|addr| println!("{addr:04X}")),
+            ("b", [addr]) => {
+                self.breakpoints.insert(*addr);
+            }
+            ("db", [addr]) => {
+                self.breakpoints.remove(addr);
+            }
+            ("w", []) => self
+                .cpu
+                .memory()
+                .watchpoints
+                .iter()
+                .for_each(|addr| println!("{addr:04X}")),
+            ("w", [addr]) => {
+                self.cpu.memory_mut().watchpoints.insert(*addr);
+            }
+            ("dw", [addr]) => {
+                self.cpu.memory_mut().watchpoints.remove(addr);
+            }
+            ("r", []) => self.show_registers(),
+            ("x", [addr]) => self.dump(*addr, 0x40),
+            ("x", [addr, len]) => self.dump(*addr, *len),
+            ("l", []) => self.list(pc, 8),
+            ("l", [addr]) => self.list(*addr, 8),
+            ("l", [addr, count]) => self.list(*addr, *count as usize),
+            ("t", []) => self.show_history(16),
+            ("t", [count]) => self.show_history(*count as usize),
+            ("poke", [_, bytes @ ..]) if bytes.iter().any(|&byte| byte > 0xff) => {
+                println!("bytes are 00 to FF, nothing written")
+            }
+            ("poke", [addr, bytes @ ..]) if !bytes.is_empty() => {
+                for (offset, &byte) in (0..).zip(bytes) {
+                    let addr = addr.wrapping_add(offset);
+                    let memory = &mut self.cpu.memory_mut().inner;
+                    if (addr as usize) < memory.rom().len() {
+                        println!("{addr:04X} is ROM, write dropped");
+                    } else {
+                        memory.write(addr, byte as u8)?
+                    }
+                }
+            }
+            ("h", []) => println!("{HELP}"),
+            ("q", []) => return Ok(false),
+            _ => println!("{HELP}"),
+        }
+        if matches!(command, "s" | "n" | "c") {
+            self.list(self.cpu.state().pc, 1)
+        }
+        Ok(true)
+    }
+
+    fn step_n(&mut self, count: u16) -> Result<()> {
+        for _ in 0..count {
+            self.step()?;
+            if let Some(reason) = self.stop_reason() {
+                println!("{reason}");
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn load(args: &[String]) -> Result<Cpu> {
+    let (com, path) = match args {
+        [path] => (false, path),
+        [flag, path] if flag == "--com" => (true, path),
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2)
+        }
+    };
+    let image = fs::read(path)?;
+    let memory = if com {
+        load_com(&image)?
+    } else {
+        let ram = vec![0; 0x10000usize.saturating_sub(image.len())];
+        RomRam::new(image, ram)
+    };
+    let (mut cpu, _) = Cpu8080::with_memory(
+        Watched {
+            inner: memory,
+            watchpoints: BTreeSet::new(),
+            hits: vec![],
+        },
+        (),
+    );
+    if com {
+        cpu.enable_cpm(Bdos::new(io::stdout()))
+    }
+    Ok(cpu)
+}
+
+fn main() -> Result<()> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let mut debugger = Debugger {
+        cpu: load(&args)?,
+        breakpoints: BTreeSet::new(),
+        history: TraceBuffer::new(256),
+    };
+    debugger.cpu.set_tracer(debugger.history.clone());
+    debugger.list(debugger.cpu.state().pc, 1);
+    let mut last = String::new();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = line;
+        }
+        let mut words = last.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let words = words.collect::<Vec<_>>();
+        let (register, words) = match (command, words.as_slice()) {
+            ("set", [register, rest @ ..]) => (Some(register.to_lowercase()), rest),
+            (_, words) => (None, words),
+        };
+        let Some(args) = words
+            .iter()
+            .map(|arg| parse_number(arg))
+            .collect::<Option<Vec<_>>>()
+        else {
+            println!("{HELP}");
+            continue;
+        };
+        let result = match (register, args.as_slice()) {
+            (Some(register), [value]) => {
+                if !debugger.set_register(&register, *value) {
+                    println!("{HELP}")
+                }
+                Ok(true)
+            }
+            (Some(_), _) => {
+                println!("{HELP}");
+                Ok(true)
+            }
+            (None, args) => debugger.execute(command, args),
+        };
+        match result {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(error) => println!("error: {error}"),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::{collections::BTreeSet, env, fs, process};
+
+use i8080emulator::{disassemble, Result, Syntax, Variant};
+
+const USAGE: &str = "usage: i8080disasm [--zilog] [--8085] [--org addr] <image>
+  --zilog     Z80 style mnemonics instead of the Intel ones
+  --8085      decode the opcodes the 8085 executes differently
+  --org addr  hexadecimal address the image is loaded at, 0000H by default";
+
+struct Options {
+    syntax: Syntax,
+    variant: Variant,
+    org: u16,
+    path: String,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut syntax = Syntax::Intel;
+    let mut variant = Variant::I8080;
+    let mut org = 0;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--zilog" => syntax = Syntax::Zilog,
+            "--8085" => variant = Variant::I8085,
+            "--org" => {
+                org = u16::from_str_radix(args.next()?.trim_end_matches(['h', 'H']), 16).ok()?
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return None,
+        }
+    }
+    Some(Options {
+        syntax,
+        variant,
+        org,
+        path: path?,
+    })
+}
+
+fn main() -> Result<()> {
+    let Some(options) = parse_options(env::args().skip(1)) else {
+        eprintln!("{USAGE}");
+        process::exit(2);
+    };
+    let image = fs::read(&options.path)?;
+    let end = options.org as usize + image.len();
+    let decode = |offset: usize| disassemble(&image[offset..], options.syntax, options.variant);
+
+    // first pass: every jump and call target inside the image gets a label
+    let mut labels = BTreeSet::new();
+    let mut offset = 0;
+    while let Some(instruction) = (offset < image.len()).then(|| decode(offset)).flatten() {
+        if let Some(target) = instruction.target() {
+            if (options.org as usize..end).contains(&(target as usize)) {
+                labels.insert(target);
+            }
+        }
+        offset += instruction.len as usize;
+    }
+
+    let label = |addr: u16| labels.contains(&addr).then(|| format!("L{addr:04X}"));
+    println!("        ORG {:04X}H", options.org);
+    let mut offset = 0;
+    while offset < image.len() {
+        let addr = options.org.wrapping_add(offset as u16);
+        if let Some(name) = label(addr) {
+            println!("{name}:");
+        }
+        let (len, text) = match decode(offset) {
+            Some(instruction) if instruction.undocumented => (
+                instruction.len as usize,
+                format!("{:<20}; undocumented", instruction.render(label)),
+            ),
+            Some(instruction) => (instruction.len as usize, instruction.render(label)),
+            // the image ends in the middle of an instruction
+            None => (1, format!("DB {:02X}H", image[offset])),
+        };
+        let bytes = image[offset..offset + len]
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!("        {text:<32}; {addr:04X}  {bytes}");
+        offset += len;
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Decides how the run loop waits for the wall clock to
+/// catch up with the emulated clock at the end of a slice.
+///
+/// `emulated` is how long the slice takes on real hardware,
+/// `elapsed` is how long the host has spent emulating it.
+pub trait Pacer {
+    fn pace(&mut self, emulated: Duration, elapsed: Duration);
+}
+
+/// Sleep the thread, cheap but only as precise as the OS scheduler
+pub struct Sleep;
+
+impl Pacer for Sleep {
+    fn pace(&mut self, emulated: Duration, elapsed: Duration) {
+        if let Some(remaining) = emulated.checked_sub(elapsed) {
+            thread::sleep(remaining)
+        }
+    }
+}
+
+/// Spin until the slice is due, precise but keeps a core busy
+pub struct BusyWait;
+
+impl Pacer for BusyWait {
+    fn pace(&mut self, emulated: Duration, elapsed: Duration) {
+        if let Some(remaining) = emulated.checked_sub(elapsed) {
+            let deadline = Instant::now() + remaining;
+            while Instant::now() < deadline {
+                std::hint::spin_loop()
+            }
+        }
+    }
+}
+
+/// Any closure is a host-driven pacer, e.g. one
+/// blocking until the display's next vsync
+impl<F: FnMut(Duration, Duration)> Pacer for F {
+    fn pace(&mut self, emulated: Duration, elapsed: Duration) {
+        self(emulated, elapsed)
+    }
+}
+
+/// The speed of the run loop: it executes `slice_cycles` worth
+/// of instructions, then hands over to the pacer
+pub struct Clock {
+    frequency: u64,
+    slice_cycles: u64,
+    pacer: Box<dyn Pacer>,
+}
+
+impl Default for Clock {
+    /// 2 MHz, paced by sleeping every 1/120 second
+    fn default() -> Self {
+        Clock::new(2_000_000)
+    }
+}
+
+impl Clock {
+    /// Run at `frequency` Hz, 0 runs as fast as the host can
+    pub fn new(frequency: u64) -> Self {
+        Clock {
+            frequency,
+            slice_cycles: 16666,
+            pacer: Box::new(Sleep),
+        }
+    }
+
+    /// Number of cycles executed between two calls to the pacer
+    pub fn slice_cycles(mut self, slice_cycles: u64) -> Self {
+        self.slice_cycles = slice_cycles.max(1);
+        self
+    }
+
+    pub fn pacer(mut self, pacer: impl Pacer + 'static) -> Self {
+        self.pacer = Box::new(pacer);
+        self
+    }
+
+    pub fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    /// Change the speed on the fly, e.g. for fast-forward or slow motion
+    pub fn set_frequency(&mut self, frequency: u64) {
+        self.frequency = frequency
+    }
+
+    /// Real hardware time of `cycles`, zero when unthrottled
+    pub fn duration_of(&self, cycles: u64) -> Duration {
+        match self.frequency {
+            0 => Duration::ZERO,
+            frequency => {
+                Duration::from_nanos((cycles as u128 * 1_000_000_000 / frequency as u128) as u64)
+            }
+        }
+    }
+
+    pub(crate) fn is_slice_over(&self, cycles: u64) -> bool {
+        cycles >= self.slice_cycles
+    }
+
+    pub(crate) fn pace(&mut self, cycles: u64, elapsed: Duration) {
+        let emulated = self.duration_of(cycles);
+        self.pacer.pace(emulated, elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Clock;
+    use std::time::Duration;
+
+    #[test]
+    fn emulated_duration() {
+        let clock = Clock::default();
+        assert_eq!(clock.duration_of(16666), Duration::from_micros(8333));
+        assert_eq!(Clock::new(0).duration_of(16666), Duration::ZERO);
+        assert!(Clock::new(0).slice_cycles(0).is_slice_over(1));
+    }
+}
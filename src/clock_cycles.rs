@@ -1,4 +1,6 @@
 pub mod cycles {
+    /// Clock cycles of every opcode, conditional CALL/RET
+    /// are listed with the cost of the taken branch
     pub const CLOCK_CYCLES: [u8; 0x100] = [
         4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5,
         5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 13, 5, 10, 10, 10, 4,
@@ -7,8 +9,178 @@ pub mod cycles {
         7, 7, 7, 7, 5, 5, 5, 5, 5, 5, 7, 5, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4,
         4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
         4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 11, 10, 10, 10, 17, 11, 7, 11, 11, 10, 10,
-        10, 10, 17, 7, 11, 11, 10, 10, 10, 17, 11, 7, 11, 11, 10, 10, 10, 10, 17, 7, 11, 11, 10,
-        10, 18, 17, 11, 7, 11, 11, 5, 10, 5, 17, 17, 7, 11, 11, 10, 10, 4, 17, 11, 7, 11, 11, 5,
+        10, 17, 17, 7, 11, 11, 10, 10, 10, 17, 11, 7, 11, 11, 10, 10, 10, 17, 17, 7, 11, 11, 10,
+        10, 18, 17, 11, 7, 11, 11, 5, 10, 4, 17, 17, 7, 11, 11, 10, 10, 4, 17, 11, 7, 11, 11, 5,
         10, 4, 17, 17, 7, 11,
     ];
+
+    /// Same as `CLOCK_CYCLES`, but conditional CALL/RET are listed
+    /// with the cost of the branch not taken: 11 and 5 cycles
+    pub const CLOCK_CYCLES_NOT_TAKEN: [u8; 0x100] = {
+        let mut cycles = CLOCK_CYCLES;
+        let mut opcode = 0xc0;
+        while opcode <= 0xf8 {
+            cycles[opcode] = 5; // Rxx
+            cycles[opcode + 4] = 11; // Cxx
+            opcode += 8;
+        }
+        cycles
+    };
+
+    /// Clock cycles of every opcode on the 8085, conditional
+    /// JMP/CALL/RET are listed with the cost of the taken branch
+    pub const CLOCK_CYCLES_8085: [u8; 0x100] = {
+        let mut cycles = CLOCK_CYCLES;
+        let mut opcode = 0;
+        while opcode < 0x100 {
+            let z = opcode & 0x07;
+            cycles[opcode] = match opcode {
+                // INX and DCX
+                0x00..=0x3f if z == 3 => 6,
+                // MOV r,r, INR r and DCR r
+                0x00..=0x7f if CLOCK_CYCLES[opcode] == 5 => 4,
+                // Rcc, Jcc, Ccc and RST
+                0xc0..=0xff if z == 0 || z == 7 => 12,
+                0xc0..=0xff if z == 2 => 10,
+                0xc0..=0xff if z == 4 => 18,
+                0xc5 | 0xd5 | 0xe5 | 0xf5 => 12,
+                0xcd => 18,
+                0xe3 => 16,
+                0xe9 | 0xf9 => 6,
+                0x76 => 5,
+                // RIM, SIM and the undocumented instructions
+                0x20 | 0x30 => 4,
+                0x10 => 7,
+                0x08 | 0x18 | 0x28 | 0x38 | 0xd9 | 0xed => 10,
+                0xcb => 12,
+                0xdd | 0xfd => 10,
+                _ => CLOCK_CYCLES[opcode],
+            };
+            opcode += 1;
+        }
+        cycles
+    };
+
+    /// Same as `CLOCK_CYCLES_8085`, but conditional JMP/CALL/RET
+    /// (and RSTV, JNK, JK) are listed with the cost of the branch not taken
+    pub const CLOCK_CYCLES_NOT_TAKEN_8085: [u8; 0x100] = {
+        let mut cycles = CLOCK_CYCLES_8085;
+        let mut opcode = 0xc0;
+        while opcode <= 0xf8 {
+            cycles[opcode] = 6; // Rxx
+            cycles[opcode + 2] = 7; // Jxx
+            cycles[opcode + 4] = 9; // Cxx
+            opcode += 8;
+        }
+        cycles[0xcb] = 6;
+        cycles[0xdd] = 7;
+        cycles[0xfd] = 7;
+        cycles
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cycles::*;
+
+    /// Timings from the Intel 8080 Microcomputer Systems User's Manual
+    #[test]
+    fn published_8080_timings() {
+        let published: &[(&[u8], u8)] = &[
+            (
+                &[0x00, 0x07, 0x0f, 0x17, 0x1f, 0x27, 0x2f, 0x37, 0x3f, 0xeb],
+                4,
+            ),
+            (&[0xf3, 0xfb, 0x80, 0x87, 0xa8, 0xbf], 4),
+            (
+                &[0x03, 0x0b, 0x33, 0x3b, 0x04, 0x3d, 0x40, 0x7f, 0xe9, 0xf9],
+                5,
+            ),
+            (
+                &[
+                    0x02, 0x0a, 0x12, 0x1a, 0x06, 0x3e, 0x46, 0x70, 0x76, 0x86, 0xc6,
+                ],
+                7,
+            ),
+            (
+                &[
+                    0x01, 0x09, 0x34, 0x35, 0x36, 0xc1, 0xc2, 0xc3, 0xc9, 0xd3, 0xdb,
+                ],
+                10,
+            ),
+            (&[0xc5, 0xc7, 0xff], 11),
+            (&[0x32, 0x3a], 13),
+            (&[0x22, 0x2a], 16),
+            (&[0xcd], 17),
+            (&[0xe3], 18),
+        ];
+        for &(opcodes, cycles) in published {
+            for &opcode in opcodes {
+                assert_eq!(CLOCK_CYCLES[opcode as usize], cycles, "{opcode:#04x}");
+                assert_eq!(
+                    CLOCK_CYCLES_NOT_TAKEN[opcode as usize], cycles,
+                    "{opcode:#04x}"
+                );
+            }
+        }
+        for condition in 0..8 {
+            let (ret, call) = (0xc0 | condition << 3, 0xc4 | condition << 3);
+            assert_eq!((CLOCK_CYCLES[ret], CLOCK_CYCLES_NOT_TAKEN[ret]), (11, 5));
+            assert_eq!((CLOCK_CYCLES[call], CLOCK_CYCLES_NOT_TAKEN[call]), (17, 11));
+        }
+    }
+
+    /// Timings from the Intel 8085AH data sheet
+    #[test]
+    fn published_8085_timings() {
+        let published: &[(&[u8], u8)] = &[
+            (&[0x00, 0x07, 0x27, 0x2f, 0xeb, 0xf3, 0xfb, 0x20, 0x30], 4),
+            (&[0x04, 0x3d, 0x40, 0x7f, 0x80, 0xbf], 4),
+            (&[0x76], 5),
+            (&[0x03, 0x0b, 0x33, 0x3b, 0xe9, 0xf9], 6),
+            (&[0x02, 0x0a, 0x06, 0x46, 0x70, 0x86, 0xc6, 0x10], 7),
+            (
+                &[
+                    0x01, 0x09, 0x34, 0x35, 0x36, 0xc1, 0xc3, 0xc9, 0xd3, 0xdb, 0x08, 0x28, 0xd9,
+                    0xed,
+                ],
+                10,
+            ),
+            (&[0xc5, 0xf5, 0xc7, 0xff], 12),
+            (&[0x32, 0x3a], 13),
+            (&[0x22, 0x2a, 0xe3], 16),
+            (&[0xcd], 18),
+        ];
+        for &(opcodes, cycles) in published {
+            for &opcode in opcodes {
+                assert_eq!(CLOCK_CYCLES_8085[opcode as usize], cycles, "{opcode:#04x}");
+                assert_eq!(
+                    CLOCK_CYCLES_NOT_TAKEN_8085[opcode as usize], cycles,
+                    "{opcode:#04x}"
+                );
+            }
+        }
+        for condition in 0..8 {
+            let (ret, jump, call) = (
+                0xc0 | condition << 3,
+                0xc2 | condition << 3,
+                0xc4 | condition << 3,
+            );
+            assert_eq!(taken_and_not(ret), (12, 6));
+            assert_eq!(taken_and_not(jump), (10, 7));
+            assert_eq!(taken_and_not(call), (18, 9));
+        }
+        // RSTV, JNK and JK
+        assert_eq!(
+            [0xcb, 0xdd, 0xfd].map(taken_and_not),
+            [(12, 6), (10, 7), (10, 7)]
+        );
+    }
+
+    fn taken_and_not(opcode: usize) -> (u8, u8) {
+        (
+            CLOCK_CYCLES_8085[opcode],
+            CLOCK_CYCLES_NOT_TAKEN_8085[opcode],
+        )
+    }
 }
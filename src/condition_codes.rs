@@ -4,9 +4,15 @@ use std::{
 };
 
 #[repr(transparent)]
-#[derive(Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct ConditionCodes(u8);
 
+impl From<u8> for ConditionCodes {
+    fn from(value: u8) -> Self {
+        Self(value)
+    }
+}
+
 impl Deref for ConditionCodes {
     type Target = u8;
     fn deref(&self) -> &Self::Target {
@@ -53,13 +59,55 @@ macro_rules! generate_bit_operations {
 }
 
 /// 0---0---0---0---0---0---0---0
-/// N/A N/A N/A AC  P  Z   S   C
+/// N/A  K   V  AC  P  Z   S   C
+///
+/// This is the emulator's own layout, software sees the
+/// flags as laid out by `to_psw`. V (overflow) and K (signed
+/// underflow) only exist on the 8085, see `to_psw_8085`
 impl ConditionCodes {
     generate_bit_operations![
         (set_carry, is_carry, is_carry_set, 0),
         (set_sign, is_sign, is_sign_set, 1),
         (set_zero, is_zero, is_zero_set, 2),
         (set_parity, is_parity, is_parity_set, 3),
-        (set_aux_carry, is_aux_carry, is_aux_carry_set, 4)
+        (set_aux_carry, is_aux_carry, is_aux_carry_set, 4),
+        (set_overflow, is_overflow, is_overflow_set, 5),
+        (set_k, is_k, is_k_set, 6)
     ];
+
+    /// The flags byte pushed by PUSH PSW:
+    /// S Z 0 AC 0 P 1 C, from bit 7 to bit 0
+    pub fn to_psw(&self) -> u8 {
+        (self.is_sign_set() as u8) << 7
+            | (self.is_zero_set() as u8) << 6
+            | (self.is_aux_carry_set() as u8) << 4
+            | (self.is_parity_set() as u8) << 2
+            | 0x02
+            | self.is_carry_set() as u8
+    }
+
+    /// The flags popped by POP PSW, bits 5, 3 and 1 are ignored
+    pub fn from_psw(psw: u8) -> Self {
+        let mut condition_codes = ConditionCodes::default();
+        condition_codes.set_sign(psw & 0x80 != 0);
+        condition_codes.set_zero(psw & 0x40 != 0);
+        condition_codes.set_aux_carry(psw & 0x10 != 0);
+        condition_codes.set_parity(psw & 0x04 != 0);
+        condition_codes.set_carry(psw & 0x01 != 0);
+        condition_codes
+    }
+
+    /// The flags byte pushed by PUSH PSW on the 8085:
+    /// S Z K AC 0 P V C, from bit 7 to bit 0
+    pub fn to_psw_8085(&self) -> u8 {
+        self.to_psw() & !0x02 | (self.is_k_set() as u8) << 5 | (self.is_overflow_set() as u8) << 1
+    }
+
+    /// The flags popped by POP PSW on the 8085, bit 3 is ignored
+    pub fn from_psw_8085(psw: u8) -> Self {
+        let mut condition_codes = Self::from_psw(psw);
+        condition_codes.set_k(psw & 0x20 != 0);
+        condition_codes.set_overflow(psw & 0x02 != 0);
+        condition_codes
+    }
 }
@@ -0,0 +1,65 @@
+use std::io::Write;
+
+use crate::{memory::RomRam, MemoryBus, MemoryOutOfBounds, Result};
+
+/// Programs `CALL 5` with the BDOS function number in reg_c
+pub const BDOS_ENTRY: u16 = 0x0005;
+
+/// Jumping to 0 re-enters CP/M, i.e. the program has finished
+pub const WARM_BOOT: u16 = 0x0000;
+
+/// Transient programs (.COM) are loaded and started here
+pub const TPA_START: u16 = 0x0100;
+
+/// Where the BDOS lives in our fake CP/M, the word at 0x0006
+/// is used by many programs as the top of the usable memory
+const BDOS_BASE: u16 = 0xff00;
+
+/// A minimal CP/M personality: the console BDOS functions
+/// needed by diagnostic and test programs
+pub struct Bdos {
+    console: Box<dyn Write>,
+}
+
+impl Bdos {
+    pub fn new(console: impl Write + 'static) -> Self {
+        Bdos {
+            console: Box::new(console),
+        }
+    }
+
+    /// Function 2 prints the character in reg_e,
+    /// function 9 prints the `$`-terminated string at DE,
+    /// other functions are ignored
+    pub(crate) fn call(&mut self, function: u8, de: u16, memory: &impl MemoryBus) -> Result<()> {
+        match function {
+            2 => self.console.write_all(&[de as u8])?,
+            9 => {
+                let mut msg = vec![];
+                for offset in 0..=u16::MAX {
+                    match memory.read(de.wrapping_add(offset))? {
+                        b'$' => break,
+                        c => msg.push(c),
+                    }
+                }
+                self.console.write_all(&msg)?
+            }
+            _ => (),
+        }
+        Ok(self.console.flush()?)
+    }
+}
+
+/// Lay out a .COM program in a 64 KiB address space the way CP/M does:
+/// the program at 0x0100, and a `JMP` to the BDOS at 0x0005. It is
+/// all RAM, transient programs write into their own image
+pub fn load_com(program: &[u8]) -> Result<RomRam> {
+    let mut ram = vec![0; 0x10000];
+    ram.get_mut(TPA_START as usize..TPA_START as usize + program.len())
+        .ok_or(MemoryOutOfBounds)?
+        .copy_from_slice(program);
+    let [lo, hi] = BDOS_BASE.to_le_bytes();
+    ram[BDOS_ENTRY as usize..BDOS_ENTRY as usize + 3].copy_from_slice(&[0xc3, lo, hi]);
+    ram[BDOS_BASE as usize] = 0xc9; // RET
+    Ok(RomRam::new(vec![], ram))
+}
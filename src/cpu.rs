@@ -1,25 +1,32 @@
-use core::panic;
 use std::{
     mem,
-    ops::{Deref, DerefMut},
-    thread,
-    time::{Duration, Instant},
-};
-
-#[cfg(not(feature = "cpu_diag"))]
-use std::{
-    ffi::c_void,
+    ops::DerefMut,
     sync::mpsc::{channel, Receiver, Sender},
+    time::Instant,
 };
 
-#[cfg(not(feature = "cpu_diag"))]
-use crate::{IoCallbacks, Message};
+mod i8085;
+
+pub use i8085::InterruptPin;
 
-use crate::{condition_codes::ConditionCodes, MemoryOutOfBounds, Result, CLOCK_CYCLES};
+pub(crate) use i8085::Pins8085;
 
-pub struct Cpu8080 {
-    ram: Vec<u8>,
-    rom: Vec<u8>,
+use crate::{
+    clock::Clock,
+    condition_codes::ConditionCodes,
+    cpm::{Bdos, BDOS_ENTRY, TPA_START, WARM_BOOT},
+    cpu_state::CpuState,
+    io::{CallbackIo, PortIo},
+    memory::{MemoryBus, RomRam},
+    scheduler::{EventAction, EventId, Scheduler},
+    trace::{TraceRecord, Tracer},
+    EmulatorErrors, Message, Result, CLOCK_CYCLES, CLOCK_CYCLES_8085, CLOCK_CYCLES_NOT_TAKEN,
+    CLOCK_CYCLES_NOT_TAKEN_8085,
+};
+
+pub struct Cpu8080<M: MemoryBus = RomRam, P: PortIo = CallbackIo> {
+    memory: M,
+    io: P,
     sp: u16,
     pc: u16,
     reg_a: u8,
@@ -31,12 +38,93 @@ pub struct Cpu8080 {
     reg_l: u8,
     conditon_codes: ConditionCodes,
     interrupt_enabled: bool,
-    #[cfg(not(feature = "cpu_diag"))]
-    io_object: *const c_void,
-    #[cfg(not(feature = "cpu_diag"))]
-    io_callbacks: IoCallbacks,
-    #[cfg(not(feature = "cpu_diag"))]
+    /// EI enables interrupts after the following instruction
+    pub(crate) interrupt_delayed: bool,
+    /// The INT line: the instruction the device places on the bus once
+    /// acknowledged and whether INTE is left enabled, held until then
+    pub(crate) interrupt_request: Option<([u8; 3], bool)>,
+    variant: Variant,
+    pub(crate) pins: Pins8085,
+    halted: bool,
+    /// An instruction placed on the bus by an interrupting
+    /// device, fetched instead of memory at the given address
+    injected: Option<(u16, [u8; 3])>,
     message_receiver: Receiver<Message>,
+    bdos: Option<Bdos>,
+    exited: bool,
+    clock: Clock,
+    /// Clock cycles executed so far, the time base of `scheduler`
+    cycles: u64,
+    scheduler: Scheduler<P>,
+    tracer: Option<Box<dyn Tracer>>,
+    halt_handler: Option<Box<dyn FnMut()>>,
+}
+
+/// CALL and its undocumented aliases, as a pattern
+macro_rules! unconditional_call {
+    () => {
+        0xcd | 0xdd | 0xed | 0xfd
+    };
+}
+
+/// Size in bytes of the instruction starting with `opcode` on `variant`
+pub fn instruction_length(opcode: u8, variant: Variant) -> u16 {
+    match (opcode, variant) {
+        (0xcb | 0xd9 | 0xed, Variant::I8085) => 1,
+        (0x28 | 0x38, Variant::I8085) => 2,
+        (0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2a | 0x32 | 0x3a, _) => 3,
+        (
+            0xc2 | 0xc3 | 0xc4 | 0xca | 0xcb | 0xcc | 0xcd | 0xd2 | 0xd4 | 0xda | 0xdc | 0xdd
+            | 0xe2 | 0xe4 | 0xea | 0xec | 0xed | 0xf2 | 0xf4 | 0xfa | 0xfc | 0xfd,
+            _,
+        ) => 3,
+        (0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e, _) => 2,
+        (0xc6 | 0xce | 0xd3 | 0xd6 | 0xdb | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe, _) => 2,
+        _ => 1,
+    }
+}
+
+/// Whether `opcode` pushes a return address on `variant`: CALL, the
+/// conditional calls and RST, plus the CALL aliases on the 8080 and RSTV
+/// on the 8085, where the aliases are JNK, LHLX and JK
+pub fn is_call(opcode: u8, variant: Variant) -> bool {
+    let alias = match variant {
+        Variant::I8080 => matches!(opcode, unconditional_call!()),
+        Variant::I8085 => matches!(opcode, 0xcb | 0xcd),
+    };
+    alias || opcode & 0xc7 == 0xc4 || opcode & 0xc7 == 0xc7
+}
+
+/// The RST instruction vectoring to `irq_no` * 8
+pub fn rst_instruction(irq_no: u8) -> Result<u8> {
+    match irq_no {
+        0..=7 => Ok(0xc7 | irq_no << 3),
+        _ => Err(EmulatorErrors::UnsupportedInterrupt(irq_no)),
+    }
+}
+
+/// The CPU to emulate, see `Cpu8080::with_variant`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    I8080,
+    /// Adds RIM/SIM, the TRAP and RST 5.5/6.5/7.5 inputs, the serial
+    /// pins, its own timings, and the undocumented instructions and
+    /// V/K flags in place of the 8080 undocumented opcodes
+    I8085,
+}
+
+/// What a single `Cpu8080::step` has executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    pub opcode: u8,
+    /// Clock cycles used, as listed in `CLOCK_CYCLES`, or in
+    /// `CLOCK_CYCLES_NOT_TAKEN` when a conditional CALL/RET is not taken,
+    /// plus the cycles of the interrupts fired by scheduled events
+    pub cycles: u64,
+    /// Program counter after the instruction
+    pub pc: u16,
 }
 
 macro_rules! generate_move_between_reg_and_memory {
@@ -44,12 +132,12 @@ macro_rules! generate_move_between_reg_and_memory {
         $(
             fn $move_from_memory(&mut self) -> Result<()> {
                 let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-                Ok(self.$reg = self.load_byte_from_memory(mem_addr.into())?)
+                Ok(self.$reg = self.load_byte_from_memory(mem_addr)?)
             }
 
             fn $move_to_memory(&mut self) -> Result<()> {
                 let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-                self.store_to_ram(mem_addr.into(), self.$reg)
+                self.store_to_memory(mem_addr, self.$reg)
             }
         )*
     };
@@ -58,29 +146,29 @@ macro_rules! generate_move_between_reg_and_memory {
 macro_rules! generate_call_jump_return_on_condition {
     ( $( ($call:ident, $jump:ident, $return:ident, $condition:ident) ),* ) => {
         $(
-            fn $call(&mut self, $condition: bool) -> Result<()> {
+            fn $call(&mut self, $condition: bool) -> Result<bool> {
                 if $condition {
                     self.call()?;
                 } else {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
-                Ok(())
+                Ok($condition)
             }
 
-            fn $jump(&mut self, $condition: bool) -> Result<()> {
+            fn $jump(&mut self, $condition: bool) -> Result<bool> {
                 if $condition {
                     self.jmp()?;
                 } else {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
-                Ok(())
+                Ok($condition)
             }
 
-            fn $return(&mut self, $condition: bool) -> Result<()> {
+            fn $return(&mut self, $condition: bool) -> Result<bool> {
                 if $condition {
                     self.ret()?;
                 }
-                Ok(())
+                Ok($condition)
             }
         )*
     };
@@ -91,7 +179,7 @@ macro_rules! generate_load_data_into_reg_pair {
         $(
             fn $func(&mut self) -> Result<()> {
                 [self.$reg_lo, self.$reg_hi] = self.load_d16_operand()?;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
                 Ok(())
             }
         )*
@@ -102,19 +190,19 @@ macro_rules! generate_inc_dec_reg_pair {
     ( $( ($func:ident, $reg_hi:ident, $reg_lo:ident, $value:expr) ),* ) => {
         $(
             fn $func(&mut self) {
-                let pair_value = u16::from_le_bytes([self.$reg_lo, self.$reg_hi]) as u32;
-                let value = $value as u32;
-                [.., self.$reg_hi, self.$reg_lo] = (pair_value + value).to_be_bytes();
+                let pair_value = u16::from_le_bytes([self.$reg_lo, self.$reg_hi]);
+                let result = self.add_to_pair(pair_value, $value);
+                [self.$reg_lo, self.$reg_hi] = result.to_le_bytes();
             }
         )*
     };
 }
 
 macro_rules! generate_inc_dec_reg {
-    ( $( ($func:ident, $reg:ident, $value:expr) ),* ) => {
+    ( $( ($func:ident, $reg:ident, $value:expr, $carry:expr) ),* ) => {
         $(
             fn $func(&mut self) {
-                self.$reg = self.set_condition_bits(self.$reg.into(), $value.into()) as u8;
+                self.$reg = self.set_condition_bits(self.$reg, $value, $carry) as u8;
             }
         )*
     };
@@ -124,50 +212,39 @@ macro_rules! generate_push_and_pop_reg_pair {
     ( $( ($push:ident, $pop:ident, $reg_hi:ident, $reg_lo:ident) ),* ) => {
         $(
             fn $push(&mut self) -> Result<()> {
-                self.store_to_ram((self.sp - 1).into(), self.$reg_hi)?;
-                self.store_to_ram((self.sp - 2).into(), self.$reg_lo)?;
-                self.sp -= 2;
+                self.store_to_memory(self.sp.wrapping_sub(1), self.$reg_hi)?;
+                self.store_to_memory(self.sp.wrapping_sub(2), self.$reg_lo)?;
+                self.sp = self.sp.wrapping_sub(2);
                 Ok(())
             }
 
             fn $pop(&mut self) -> Result<()> {
-                let addr_lo = self.load_byte_from_memory(self.sp.into())?;
-                let addr_hi = self.load_byte_from_memory((self.sp + 1).into())?;
+                let addr_lo = self.load_byte_from_memory(self.sp)?;
+                let addr_hi = self.load_byte_from_memory(self.sp.wrapping_add(1))?;
                 (self.$reg_lo, self.$reg_hi) = (addr_lo, addr_hi);
-                self.sp += 2;
+                self.sp = self.sp.wrapping_add(2);
                 Ok(())
             }
         )*
     };
 }
 
-impl Cpu8080 {
-    #[cfg(feature = "cpu_diag")]
-    pub fn cpudiag_new(rom: Vec<u8>, ram: Vec<u8>) -> Self {
-        Cpu8080 {
-            reg_a: 0,
-            reg_b: 0,
-            reg_c: 0,
-            reg_d: 0,
-            reg_e: 0,
-            reg_h: 0,
-            reg_l: 0,
-            sp: 0,
-            pc: 0,
-            rom,
-            ram,
-            conditon_codes: ConditionCodes::default(),
-            interrupt_enabled: false,
-        }
+impl<P: PortIo> Cpu8080<RomRam, P> {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>, io: P) -> (Self, Sender<Message>) {
+        Self::with_memory(RomRam::new(rom, ram), io)
+    }
+
+    pub fn get_ram(&self) -> &[u8] {
+        self.memory.ram()
     }
+}
 
-    #[cfg(not(feature = "cpu_diag"))]
-    pub fn new(
-        rom: Vec<u8>,
-        ram: Vec<u8>,
-        io_callbacks: IoCallbacks,
-        io_object: *const c_void,
-    ) -> (Self, Sender<Message>) {
+impl<M: MemoryBus, P: PortIo> Cpu8080<M, P> {
+    pub fn with_memory(memory: M, io: P) -> (Self, Sender<Message>) {
+        Self::with_variant(memory, io, Variant::I8080)
+    }
+
+    pub fn with_variant(memory: M, io: P, variant: Variant) -> (Self, Sender<Message>) {
         let (message_sender, message_receiver) = channel();
         (
             Cpu8080 {
@@ -180,65 +257,82 @@ impl Cpu8080 {
                 reg_l: 0,
                 sp: 0,
                 pc: 0,
-                rom,
-                ram,
-                io_object,
+                memory,
+                io,
                 conditon_codes: ConditionCodes::default(),
                 interrupt_enabled: false,
-                io_callbacks,
+                interrupt_delayed: false,
+                interrupt_request: None,
+                variant,
+                pins: Pins8085::default(),
+                halted: false,
+                injected: None,
                 message_receiver,
+                bdos: None,
+                exited: false,
+                clock: Clock::default(),
+                cycles: 0,
+                scheduler: Scheduler::new(),
+                tracer: None,
+                halt_handler: None,
             },
             message_sender,
         )
     }
 
     fn add(&mut self, reg: u8) {
-        let result = self.set_condition_bits(self.reg_a.into(), reg.into());
+        let result = self.set_condition_bits(self.reg_a, reg, false);
         self.conditon_codes.set_carry(result > u8::MAX.into());
         self.reg_a = result as u8;
     }
 
     fn sub(&mut self, reg: u8) {
-        let result = self.set_condition_bits(self.reg_a.into(), reg.wrapping_neg().into());
-        self.conditon_codes.set_carry(self.reg_a < reg);
+        let result = self.set_condition_bits(self.reg_a, !reg, true);
+        // the carry flag is the borrow, the opposite of the adder's carry
+        self.conditon_codes.set_carry(result <= u8::MAX.into());
         self.reg_a = result as u8;
     }
 
     fn adc(&mut self, reg: u8) {
-        let carry = if self.conditon_codes.is_carry_set() {
-            1
-        } else {
-            0
-        };
-        let result = self.set_condition_bits(self.reg_a.into(), reg as u16 + carry);
+        let carry = self.conditon_codes.is_carry_set();
+        let result = self.set_condition_bits(self.reg_a, reg, carry);
         self.conditon_codes.set_carry(result > u8::MAX.into());
         self.reg_a = result as u8;
     }
 
     fn sbb(&mut self, reg: u8) {
-        let carry: u8 = if self.conditon_codes.is_carry_set() {
-            1
-        } else {
-            0
-        };
-        let result = self.set_condition_bits(
-            self.reg_a.into(),
-            reg.wrapping_neg() as u16 + carry.wrapping_neg() as u16,
-        );
-        self.conditon_codes.set_carry(self.reg_a < reg);
+        let borrow = self.conditon_codes.is_carry_set();
+        let result = self.set_condition_bits(self.reg_a, !reg, !borrow);
+        self.conditon_codes.set_carry(result <= u8::MAX.into());
         self.reg_a = result as u8;
     }
 
-    fn set_condition_bits(&mut self, value1: u16, value2: u16) -> u16 {
-        // Z, S, P, AC
-        let result = value1 + value2;
+    /// Z, S, P and AC of `value1 + value2 + carry`, subtractions go
+    /// through the same adder with the complement of the subtrahend,
+    /// so AC is the carry out of bit 3 either way. V and K are only
+    /// visible on the 8085: V is the signed overflow, K is S xor V
+    fn set_condition_bits(&mut self, value1: u8, value2: u8, carry: bool) -> u16 {
+        let result = value1 as u16 + value2 as u16 + carry as u16;
         let lsb = result as u8;
         self.conditon_codes.set_zero(lsb == 0);
         self.conditon_codes.set_sign(lsb >= 0x80);
-        self.conditon_codes.set_parity(lsb.count_ones() % 2 == 0);
-        let aux_carry = result & 0xf;
-        let is_aux_carry = aux_carry < (value1 & 0xf) && aux_carry < (value2 & 0xf);
-        self.conditon_codes.set_aux_carry(is_aux_carry);
+        self.conditon_codes
+            .set_parity(lsb.count_ones().is_multiple_of(2));
+        self.conditon_codes
+            .set_aux_carry((value1 & 0xf) + (value2 & 0xf) + carry as u8 > 0xf);
+        let overflow = (value1 ^ lsb) & (value2 ^ lsb) & 0x80 != 0;
+        self.conditon_codes.set_overflow(overflow);
+        self.conditon_codes.set_k(overflow != (lsb >= 0x80));
+        result
+    }
+
+    /// INX and DCX, K tells the 8085 the pair has wrapped around
+    fn add_to_pair(&mut self, pair_value: u16, value: u16) -> u16 {
+        let (result, wrapped) = match value {
+            1 => (pair_value.wrapping_add(1), pair_value == 0xffff),
+            _ => (pair_value.wrapping_add(value), pair_value == 0x0000),
+        };
+        self.conditon_codes.set_k(wrapped);
         result
     }
 
@@ -275,24 +369,12 @@ impl Cpu8080 {
         self.conditon_codes.set_carry(carry == 1);
     }
 
-    /// It is allowed to load content from either ROM or RAM
-    fn load_byte_from_memory(&self, addr: usize) -> Result<u8> {
-        if addr >= self.rom.len() {
-            Ok(*self
-                .ram
-                .get(addr - self.rom.len())
-                .ok_or(MemoryOutOfBounds)?)
-        } else {
-            Ok(*self.rom.get(addr).ok_or(MemoryOutOfBounds)?)
-        }
+    fn load_byte_from_memory(&self, addr: u16) -> Result<u8> {
+        self.memory.read(addr)
     }
 
-    /// It is only allowed to write to RAM, we shall never write to ROM
-    fn store_to_ram(&mut self, addr: usize, value: u8) -> Result<()> {
-        if let Some(content) = self.ram.get_mut(addr - self.rom.len()) {
-            *content = value
-        }
-        Ok(())
+    fn store_to_memory(&mut self, addr: u16, value: u8) -> Result<()> {
+        self.memory.write(addr, value)
     }
 
     fn adi(&mut self) -> Result<()> {
@@ -309,28 +391,28 @@ impl Cpu8080 {
 
     fn add_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.add(value);
         Ok(())
     }
 
     fn sub_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.sub(value);
         Ok(())
     }
 
     fn adc_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.adc(value);
         Ok(())
     }
 
     fn sbb_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.sbb(value);
         Ok(())
     }
@@ -348,8 +430,11 @@ impl Cpu8080 {
     }
 
     fn and(&mut self, value: u8) {
+        // AC is the OR of bit 3 of both operands, always set on the 8085
+        let aux_carry = (self.reg_a | value) & 0x08 != 0 || self.variant == Variant::I8085;
         self.reg_a &= value;
         self.logical_condtion_set();
+        self.conditon_codes.set_aux_carry(aux_carry);
     }
 
     fn ani(&mut self) -> Result<()> {
@@ -360,7 +445,7 @@ impl Cpu8080 {
 
     fn ana_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.and(value);
         Ok(())
     }
@@ -378,7 +463,7 @@ impl Cpu8080 {
 
     fn xra_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.xor(value);
         Ok(())
     }
@@ -389,7 +474,9 @@ impl Cpu8080 {
         self.conditon_codes.set_sign(self.reg_a >= 0x80);
         self.conditon_codes.set_aux_carry(false);
         self.conditon_codes
-            .set_parity(self.reg_a.count_ones() % 2 == 0);
+            .set_parity(self.reg_a.count_ones().is_multiple_of(2));
+        self.conditon_codes.set_overflow(false);
+        self.conditon_codes.set_k(false);
     }
 
     fn or(&mut self, value: u8) {
@@ -405,19 +492,19 @@ impl Cpu8080 {
 
     fn ora_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.or(value);
         Ok(())
     }
 
     fn cmp(&mut self, value: u8) {
-        let _ = self.set_condition_bits(self.reg_a.into(), value.wrapping_neg().into());
-        self.conditon_codes.set_carry(self.reg_a < value);
+        let result = self.set_condition_bits(self.reg_a, !value, true);
+        self.conditon_codes.set_carry(result <= u8::MAX.into());
     }
 
     fn cmp_m(&mut self) -> Result<()> {
         let mem_addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
-        let value = self.load_byte_from_memory(mem_addr.into())?;
+        let value = self.load_byte_from_memory(mem_addr)?;
         self.cmp(value);
         Ok(())
     }
@@ -438,36 +525,33 @@ impl Cpu8080 {
     ];
 
     generate_inc_dec_reg![
-        (inr_b, reg_b, 1u8),
-        (inr_c, reg_c, 1u8),
-        (inr_d, reg_d, 1u8),
-        (inr_e, reg_e, 1u8),
-        (inr_h, reg_h, 1u8),
-        (inr_l, reg_l, 1u8),
-        (inr_a, reg_a, 1u8),
-        (dcr_b, reg_b, 1u8.wrapping_neg()),
-        (dcr_c, reg_c, 1u8.wrapping_neg()),
-        (dcr_d, reg_d, 1u8.wrapping_neg()),
-        (dcr_e, reg_e, 1u8.wrapping_neg()),
-        (dcr_h, reg_h, 1u8.wrapping_neg()),
-        (dcr_l, reg_l, 1u8.wrapping_neg()),
-        (drc_a, reg_a, 1u8.wrapping_neg())
+        (inr_b, reg_b, 1, false),
+        (inr_c, reg_c, 1, false),
+        (inr_d, reg_d, 1, false),
+        (inr_e, reg_e, 1, false),
+        (inr_h, reg_h, 1, false),
+        (inr_l, reg_l, 1, false),
+        (inr_a, reg_a, 1, false),
+        (dcr_b, reg_b, !1, true),
+        (dcr_c, reg_c, !1, true),
+        (dcr_d, reg_d, !1, true),
+        (dcr_e, reg_e, !1, true),
+        (dcr_h, reg_h, !1, true),
+        (dcr_l, reg_l, !1, true),
+        (drc_a, reg_a, !1, true)
     ];
 
     fn inr_m(&mut self) -> Result<()> {
-        let addr: usize = u16::from_le_bytes([self.reg_l, self.reg_h]).into();
-        let value = self.set_condition_bits(self.load_byte_from_memory(addr)?.into(), 1) as u8;
-        self.store_to_ram(addr, value)?;
+        let addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
+        let value = self.set_condition_bits(self.load_byte_from_memory(addr)?, 1, false) as u8;
+        self.store_to_memory(addr, value)?;
         Ok(())
     }
 
     fn dcr_m(&mut self) -> Result<()> {
-        let addr: usize = u16::from_le_bytes([self.reg_l, self.reg_h]).into();
-        let value = self.set_condition_bits(
-            self.load_byte_from_memory(addr)?.into(),
-            1u8.wrapping_neg().into(),
-        ) as u8;
-        self.store_to_ram(addr, value)?;
+        let addr = u16::from_le_bytes([self.reg_l, self.reg_h]);
+        let value = self.set_condition_bits(self.load_byte_from_memory(addr)?, !1, true) as u8;
+        self.store_to_memory(addr, value)?;
         Ok(())
     }
 
@@ -488,40 +572,44 @@ impl Cpu8080 {
     ];
 
     pub fn run(&mut self) -> Result<()> {
-        #[cfg(feature = "cpu_diag")]
-        {
-            self.pc = 0x100
-        }
-        // 2Mhz => 2 circles per microsecond
-        // if we run as 120Hz, 1 / 120 => 8333 microseconds
-        // we supposed to be able to run 16666 circles
         let mut start = Instant::now();
         let mut circles = 0;
-        #[cfg(not(feature = "cpu_diag"))]
         let mut pause = true;
-        while self.pc < self.rom.len() as u16 {
-            #[cfg(not(feature = "cpu_diag"))]
+        while !self.exited {
             if pause {
-                if let Message::Suspend = self.message_receiver.recv().unwrap() {
+                if let Message::Suspend = self
+                    .message_receiver
+                    .recv()
+                    .map_err(|_| EmulatorErrors::Disconnected)?
+                {
                     pause = false
                 } else {
                     continue;
                 }
-            } else if let Ok(message) = self.message_receiver.try_recv() {
+            } else if let Some(message) = self.next_message()? {
                 match message {
                     Message::Suspend => pause = true,
                     Message::Interrupt {
                         irq_no,
                         allow_nested_interrupt,
                     } => {
-                        if self.interrupt_enabled {
-                            self.rst(irq_no)?;
-                            circles += CLOCK_CYCLES[0xc7_usize] as u64
+                        // `send_message` refuses other RSTs, the loop goes on without them
+                        if let Ok(instruction) = rst_instruction(irq_no) {
+                            circles += self.interrupt(&[instruction], allow_nested_interrupt)?
+                        }
+                    }
+                    Message::InterruptInstruction {
+                        instruction,
+                        allow_nested_interrupt,
+                    } => circles += self.interrupt(&instruction, allow_nested_interrupt)?,
+                    // the 8080 has no such pin
+                    Message::SetInterruptPin { pin, level } => {
+                        if self.variant == Variant::I8085 {
+                            self.set_interrupt_pin(pin, level)?
                         }
-                        self.interrupt_enabled = allow_nested_interrupt
                     }
                     Message::Restart => {
-                        self.ram.fill(0);
+                        self.memory.reset();
                         self.pc = 0;
                         self.reg_a = 0;
                         self.reg_b = 0;
@@ -530,19 +618,34 @@ impl Cpu8080 {
                         self.reg_e = 0;
                         self.reg_h = 0;
                         self.interrupt_enabled = false;
+                        self.interrupt_delayed = false;
+                        self.interrupt_request = None;
+                        self.pins = Pins8085::default();
+                        self.halted = false;
                         *self.conditon_codes.deref_mut() = 0;
                     }
                     Message::Shutdown => {
                         break;
                     }
+                    Message::SetFrequency { frequency } => self.clock.set_frequency(frequency),
+                    // SAFETY: the app keeps the buffers valid until completion
+                    Message::SaveState {
+                        buffer,
+                        len,
+                        completion,
+                        context,
+                    } => completion(context, unsafe { self.save_state_into(buffer, len) }),
+                    Message::LoadState {
+                        buffer,
+                        len,
+                        completion,
+                        context,
+                    } => completion(context, unsafe { self.load_state_from(buffer, len) }),
                 }
             }
-            circles += self.execute()?;
-            if circles >= 16666 {
-                let time_spent = start.elapsed().as_micros();
-                if time_spent < circles as u128 / 2 {
-                    thread::sleep(Duration::from_micros(circles / 2 - time_spent as u64))
-                }
+            circles += self.step()?.cycles;
+            if self.clock.is_slice_over(circles) {
+                self.clock.pace(circles, start.elapsed());
                 circles = 0;
                 start = Instant::now();
             }
@@ -550,30 +653,367 @@ impl Cpu8080 {
         Ok(())
     }
 
-    pub fn get_ram(&self) -> &[u8] {
-        &self.ram
-    }
-
-    fn execute(&mut self) -> Result<u64> {
-        let opcode = *self.rom.get(self.pc as usize).ok_or(MemoryOutOfBounds)?;
-        #[cfg(feature = "cpu_diag")]
-        if self.pc == 5 {
-            self.call_bdos()?;
-            self.pc -= 1;
-        } else if self.pc == 0 {
-            println!("RE-ENTRY TO CP/M WARM BOOT, exiting...");
-            std::process::exit(0)
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Switch the CPU to emulate, the registers and memory are kept
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.pins = Pins8085::default();
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            reg_a: self.reg_a,
+            reg_b: self.reg_b,
+            reg_c: self.reg_c,
+            reg_d: self.reg_d,
+            reg_e: self.reg_e,
+            reg_h: self.reg_h,
+            reg_l: self.reg_l,
+            flags: self.psw(),
+            sp: self.sp,
+            pc: self.pc,
+            interrupt_enabled: self.interrupt_enabled,
+            halted: self.halted,
+            variant: self.variant,
+        }
+    }
+
+    pub fn set_state(&mut self, state: &CpuState) {
+        self.reg_a = state.reg_a;
+        self.reg_b = state.reg_b;
+        self.reg_c = state.reg_c;
+        self.reg_d = state.reg_d;
+        self.reg_e = state.reg_e;
+        self.reg_h = state.reg_h;
+        self.reg_l = state.reg_l;
+        self.conditon_codes = state.condition_codes();
+        self.sp = state.sp;
+        self.pc = state.pc;
+        self.interrupt_enabled = state.interrupt_enabled;
+        self.halted = state.halted;
+    }
+
+    pub fn io(&self) -> &P {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut P {
+        &mut self.io
+    }
+
+    /// Replace the speed and pacing of `run`, 2 MHz by default
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+
+    /// Install the CP/M personality: BDOS calls are trapped and
+    /// jumping to the warm boot entry ends the program, which
+    /// is started at the beginning of the TPA
+    pub fn enable_cpm(&mut self, bdos: Bdos) {
+        self.bdos = Some(bdos);
+        self.pc = TPA_START;
+    }
+
+    /// The program has re-entered CP/M through warm boot
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    fn trap_cpm(&mut self) -> Result<Option<u64>> {
+        let Some(bdos) = self.bdos.as_mut() else {
+            return Ok(None);
+        };
+        match self.pc {
+            WARM_BOOT => {
+                self.exited = true;
+                Ok(Some(0))
+            }
+            BDOS_ENTRY => {
+                let de = u16::from_le_bytes([self.reg_e, self.reg_d]);
+                if self.reg_c == 0 {
+                    self.exited = true;
+                } else {
+                    bdos.call(self.reg_c, de, &self.memory)?;
+                }
+                self.ret()?;
+                Ok(Some(CLOCK_CYCLES[0xc9] as u64))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// A halted CPU waits for an interrupt, with interrupts
+    /// disabled only a restart (or the TRAP of the 8085) can bring it back
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Nothing but a message can wake up a CPU halted with interrupts
+    /// disabled, so wait for one, unless scheduled events are still to
+    /// fire: emulated time then goes on for the devices
+    fn next_message(&self) -> Result<Option<Message>> {
+        if self.is_stuck() && self.scheduler.is_empty() {
+            self.message_receiver
+                .recv()
+                .map(Some)
+                .map_err(|_| EmulatorErrors::Disconnected)
+        } else {
+            Ok(self.message_receiver.try_recv().ok())
         }
+    }
+
+    /// Call `handler` whenever the CPU halts with interrupts disabled,
+    /// replacing the previous one. `run` then waits for a message,
+    /// firing the scheduled events meanwhile
+    pub fn set_halt_handler(&mut self, handler: impl FnMut() + 'static) {
+        self.halt_handler = Some(Box::new(handler))
+    }
+
+    pub fn remove_halt_handler(&mut self) -> Option<Box<dyn FnMut()>> {
+        self.halt_handler.take()
+    }
 
-        self.pc += 1;
+    /// Halted with interrupts disabled
+    fn is_stuck(&self) -> bool {
+        self.halted && !self.interrupt_enabled
+    }
+
+    fn halt(&mut self) {
+        self.halted = true;
+        if !self.interrupt_enabled {
+            if let Some(handler) = self.halt_handler.as_mut() {
+                handler()
+            }
+        }
+    }
+
+    /// Assert the INT line: the interrupting device places `instruction`
+    /// on the bus, usually RST n, sometimes a 3-byte CALL, which is executed
+    /// without advancing the program counter once the interrupt is
+    /// acknowledged. Acknowledging disables interrupts, unless
+    /// `allow_nested_interrupt` is set.
+    ///
+    /// The request is taken right away if interrupts are enabled, otherwise
+    /// it is held, replacing any other pending one, until an instruction
+    /// following EI has been executed. Returns the clock cycles used, 0 if
+    /// the interrupt is held
+    pub fn interrupt(&mut self, instruction: &[u8], allow_nested_interrupt: bool) -> Result<u64> {
+        let Some(&opcode) = instruction.first() else {
+            return Err(EmulatorErrors::InvalidArgument(
+                "interrupt instruction is empty",
+            ));
+        };
+        let len = instruction_length(opcode, self.variant);
+        if instruction.len() < len as usize {
+            return Err(EmulatorErrors::InvalidArgument(
+                "interrupt instruction is incomplete",
+            ));
+        }
+        let mut bytes = [0; 3];
+        bytes[..len as usize].copy_from_slice(&instruction[..len as usize]);
+        self.interrupt_request = Some((bytes, allow_nested_interrupt));
+        self.acknowledge_interrupt()
+    }
+
+    /// An interrupt is asserted but not acknowledged yet
+    pub fn is_interrupt_pending(&self) -> bool {
+        self.interrupt_request.is_some()
+    }
+
+    /// Take the pending interrupt if interrupts are enabled,
+    /// returns the clock cycles used
+    fn acknowledge_interrupt(&mut self) -> Result<u64> {
+        let cycles = self.acknowledge_pin()?;
+        if cycles > 0 {
+            return Ok(cycles);
+        }
+        if !self.interrupt_enabled || self.interrupt_delayed {
+            return Ok(0);
+        }
+        let Some((bytes, allow_nested_interrupt)) = self.interrupt_request.take() else {
+            return Ok(0);
+        };
+        self.halted = false;
+        self.interrupt_enabled = false;
+        // pretend the instruction precedes the current PC, so that
+        // it ends right there and CALL/RST push the current PC
+        let len = instruction_length(bytes[0], self.variant);
+        self.pc = self.pc.wrapping_sub(len);
+        self.injected = Some((self.pc, bytes));
+        self.pc = self.pc.wrapping_add(1);
+        let result = self.execute(bytes[0]);
+        self.injected = None;
+        let cycles = result?;
+        self.interrupt_enabled |= allow_nested_interrupt;
+        self.cycles += cycles;
+        Ok(cycles)
+    }
+
+    /// Clock cycles executed since the CPU was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Fire `action` once `delay` clock cycles have been executed,
+    /// then every `period` cycles if given. Events are checked after
+    /// every instruction, so they fire at the same emulated time
+    /// whatever the host is doing
+    pub fn schedule(
+        &mut self,
+        delay: u64,
+        period: Option<u64>,
+        action: EventAction<P>,
+    ) -> Result<EventId> {
+        if period == Some(0) {
+            return Err(EmulatorErrors::InvalidArgument("event period is 0"));
+        }
+        Ok(self.scheduler.add(self.cycles + delay, period, action))
+    }
+
+    /// Returns false if the event has already fired or been cancelled
+    pub fn cancel(&mut self, id: EventId) -> bool {
+        self.scheduler.cancel(id)
+    }
+
+    /// Returns the clock cycles used by the interrupts fired
+    fn fire_due_events(&mut self) -> Result<u64> {
+        let mut cycles = 0;
+        while let Some(mut event) = self.scheduler.take_due(self.cycles) {
+            match &mut event.action {
+                EventAction::Interrupt {
+                    instruction,
+                    allow_nested_interrupt,
+                } => cycles += self.interrupt(&instruction[..], *allow_nested_interrupt)?,
+                EventAction::Device(update) => update(&mut self.io),
+            }
+            self.scheduler.reschedule(event)
+        }
+        Ok(cycles)
+    }
+
+    /// Call `tracer` before every instruction executed by `step`,
+    /// replacing the previous one
+    pub fn set_tracer(&mut self, tracer: impl Tracer + 'static) {
+        self.tracer = Some(Box::new(tracer))
+    }
+
+    pub fn remove_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    fn trace(&mut self) -> Result<()> {
+        let Some(mut tracer) = self.tracer.take() else {
+            return Ok(());
+        };
+        let mut bytes = [0; 4];
+        for (offset, byte) in (0..).zip(&mut bytes) {
+            // 0 past the end of the memory
+            *byte = self.fetch(self.pc.wrapping_add(offset)).unwrap_or(0)
+        }
+        let record = TraceRecord {
+            state: self.state(),
+            bytes,
+            cycles: self.cycles,
+        };
+        let result = tracer.trace(&record);
+        self.tracer = Some(tracer);
+        result
+    }
+
+    /// Instruction bytes come from the bus, i.e. from an
+    /// interrupting device during interrupt acknowledge
+    fn fetch(&self, addr: u16) -> Result<u8> {
+        match self.injected {
+            Some((start, bytes)) if addr.wrapping_sub(start) < 3 => {
+                Ok(bytes[addr.wrapping_sub(start) as usize])
+            }
+            _ => self.load_byte_from_memory(addr),
+        }
+    }
+
+    /// Execute exactly one instruction, then the scheduled events which
+    /// are due and the pending interrupt, a halted CPU burns the cycles
+    /// of HLT without going anywhere
+    pub fn step(&mut self) -> Result<StepInfo> {
+        let (opcode, cycles) = if self.halted {
+            (0x76, self.cycles_of(0x76, true))
+        } else {
+            // the instruction following EI is under way
+            self.interrupt_delayed = false;
+            self.trace()?;
+            let opcode = self.fetch(self.pc)?;
+            let cycles = match self.trap_cpm()? {
+                Some(cycles) => cycles,
+                None => {
+                    self.pc = self.pc.wrapping_add(1);
+                    self.execute(opcode)?
+                }
+            };
+            (opcode, cycles)
+        };
+        self.cycles += cycles;
+        let cycles = cycles + self.fire_due_events()? + self.acknowledge_interrupt()?;
+        Ok(StepInfo {
+            opcode,
+            cycles,
+            pc: self.pc,
+        })
+    }
+
+    /// Execute instructions until at least `cycles` clock cycles are used,
+    /// returns the cycles actually used, which can overshoot by the last instruction
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<u64> {
+        let mut used = 0;
+        while used < cycles && !self.exited {
+            used += self.step()?.cycles;
+        }
+        Ok(used)
+    }
+
+    /// Execute instructions until the program counter reaches `pc`, or
+    /// the CPU halts with interrupts disabled, returns the clock cycles used
+    pub fn run_until(&mut self, pc: u16) -> Result<u64> {
+        let mut used = 0;
+        while self.pc != pc && !self.exited && !self.is_stuck() {
+            used += self.step()?.cycles;
+        }
+        Ok(used)
+    }
+
+    /// Returns the clock cycles used, conditional CALL/RET
+    /// cost less when the condition is not met
+    fn execute(&mut self, opcode: u8) -> Result<u64> {
+        if self.variant == Variant::I8085 {
+            if let Some(taken) = self.execute_8085(opcode)? {
+                return Ok(self.cycles_of(opcode, taken));
+            }
+        }
+        let mut taken = true;
         match opcode {
             0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0x40 | 0x49 | 0x52 | 0x5b
-            | 0x64 | 0x6d | 0x7f | 0xcb | 0xd9 | 0xdd | 0xed | 0xfd => (),
+            | 0x64 | 0x6d | 0x7f => (),
+            // undocumented aliases of JMP and RET, CALL has its own below
+            0xcb => self.jmp()?,
+            0xd9 => self.ret()?,
             0x01 => self.load_data_into_reg_pair_b()?,
-            0x02 => self.store_to_ram(
-                u16::from_le_bytes([self.reg_c, self.reg_b]).into(),
-                self.reg_a,
-            )?,
+            0x02 => {
+                self.store_to_memory(u16::from_le_bytes([self.reg_c, self.reg_b]), self.reg_a)?
+            }
             0x03 => self.inx_b(),
             0x04 => self.inr_b(),
             0x05 => self.dcr_b(),
@@ -582,7 +1022,7 @@ impl Cpu8080 {
             0x09 => self.dad(u16::from_le_bytes([self.reg_c, self.reg_b])),
             0x0a => {
                 self.reg_a =
-                    self.load_byte_from_memory(u16::from_le_bytes([self.reg_c, self.reg_b]).into())?
+                    self.load_byte_from_memory(u16::from_le_bytes([self.reg_c, self.reg_b]))?
             }
             0x0b => self.dcx_b(),
             0x0c => self.inr_c(),
@@ -590,10 +1030,9 @@ impl Cpu8080 {
             0x0e => self.reg_c = self.load_d8_operand()?,
             0x0f => self.rrc(),
             0x11 => self.load_data_into_reg_pair_d()?,
-            0x12 => self.store_to_ram(
-                u16::from_le_bytes([self.reg_e, self.reg_d]).into(),
-                self.reg_a,
-            )?,
+            0x12 => {
+                self.store_to_memory(u16::from_le_bytes([self.reg_e, self.reg_d]), self.reg_a)?
+            }
             0x13 => self.inx_d(),
             0x14 => self.inr_d(),
             0x15 => self.dcr_d(),
@@ -602,7 +1041,7 @@ impl Cpu8080 {
             0x19 => self.dad(u16::from_le_bytes([self.reg_e, self.reg_d])),
             0x1a => {
                 self.reg_a =
-                    self.load_byte_from_memory(u16::from_le_bytes([self.reg_e, self.reg_d]).into())?
+                    self.load_byte_from_memory(u16::from_le_bytes([self.reg_e, self.reg_d]))?
             }
             0x1b => self.dcx_d(),
             0x1c => self.inr_e(),
@@ -625,17 +1064,17 @@ impl Cpu8080 {
             0x2f => self.reg_a = !self.reg_a,
             0x31 => self.load_stack_pointer_from_operand()?,
             0x32 => self.sta()?,
-            0x33 => self.sp += 1,
+            0x33 => self.sp = self.add_to_pair(self.sp, 1),
             0x34 => self.inr_m()?,
             0x35 => self.dcr_m()?,
             0x36 => {
                 let imm = self.load_d8_operand()?;
-                self.store_to_ram(u16::from_le_bytes([self.reg_l, self.reg_h]).into(), imm)?
+                self.store_to_memory(u16::from_le_bytes([self.reg_l, self.reg_h]), imm)?
             }
             0x37 => self.conditon_codes.set_carry(true),
             0x39 => self.dad(self.sp),
             0x3a => self.lda()?,
-            0x3b => self.sp -= 1,
+            0x3b => self.sp = self.add_to_pair(self.sp, 1u16.wrapping_neg()),
             0x3c => self.inr_a(),
             0x3d => self.drc_a(),
             0x3e => self.reg_a = self.load_d8_operand()?,
@@ -690,7 +1129,7 @@ impl Cpu8080 {
             0x73 => self.store_reg_e_to_ram()?,
             0x74 => self.store_reg_h_to_ram()?,
             0x75 => self.store_reg_l_to_ram()?,
-            0x76 => std::process::exit(1), // HLT
+            0x76 => self.halt(),
             0x77 => self.store_reg_a_to_ram()?,
             0x78 => self.reg_a = self.reg_b,
             0x79 => self.reg_a = self.reg_c,
@@ -763,72 +1202,85 @@ impl Cpu8080 {
             0xbd => self.cmp(self.reg_l),
             0xbe => self.cmp_m()?,
             0xbf => self.cmp(self.reg_a),
-            0xc0 => self.ret_on_zero(!self.conditon_codes.is_zero_set())?,
+            0xc0 => taken = self.ret_on_zero(!self.conditon_codes.is_zero_set())?,
             0xc1 => self.pop_b()?,
-            0xc2 => self.jump_on_zero(!self.conditon_codes.is_zero_set())?,
+            0xc2 => taken = self.jump_on_zero(!self.conditon_codes.is_zero_set())?,
             0xc3 => self.jmp()?,
-            0xc4 => self.call_on_zero(!self.conditon_codes.is_zero_set())?,
+            0xc4 => taken = self.call_on_zero(!self.conditon_codes.is_zero_set())?,
             0xc5 => self.push_b()?,
             0xc6 => self.adi()?,
             0xc7 => self.rst(0)?,
-            0xc8 => self.ret_on_zero(self.conditon_codes.is_zero_set())?,
+            0xc8 => taken = self.ret_on_zero(self.conditon_codes.is_zero_set())?,
             0xc9 => self.ret()?,
-            0xca => self.jump_on_zero(self.conditon_codes.is_zero_set())?,
-            0xcc => self.call_on_zero(self.conditon_codes.is_zero_set())?,
-            0xcd => self.call()?,
+            0xca => taken = self.jump_on_zero(self.conditon_codes.is_zero_set())?,
+            0xcc => taken = self.call_on_zero(self.conditon_codes.is_zero_set())?,
+            unconditional_call!() => self.call()?,
             0xce => self.aci()?,
             0xcf => self.rst(1)?,
-            0xd0 => self.ret_on_carry(!self.conditon_codes.is_carry_set())?,
+            0xd0 => taken = self.ret_on_carry(!self.conditon_codes.is_carry_set())?,
             0xd1 => self.pop_d()?,
-            0xd2 => self.jump_on_carry(!self.conditon_codes.is_carry_set())?,
+            0xd2 => taken = self.jump_on_carry(!self.conditon_codes.is_carry_set())?,
             0xd3 => self.output()?,
-            0xd4 => self.call_on_carry(!self.conditon_codes.is_carry_set())?,
+            0xd4 => taken = self.call_on_carry(!self.conditon_codes.is_carry_set())?,
             0xd5 => self.push_d()?,
             0xd6 => self.sui()?,
             0xd7 => self.rst(2)?,
-            0xd8 => self.ret_on_carry(self.conditon_codes.is_carry_set())?,
-            0xda => self.jump_on_carry(self.conditon_codes.is_carry_set())?,
+            0xd8 => taken = self.ret_on_carry(self.conditon_codes.is_carry_set())?,
+            0xda => taken = self.jump_on_carry(self.conditon_codes.is_carry_set())?,
             0xdb => self.input()?,
-            0xdc => self.call_on_carry(self.conditon_codes.is_carry_set())?,
+            0xdc => taken = self.call_on_carry(self.conditon_codes.is_carry_set())?,
             0xde => self.sbi()?,
             0xdf => self.rst(3)?,
-            0xe0 => self.ret_on_parity(!self.conditon_codes.is_parity_set())?,
+            0xe0 => taken = self.ret_on_parity(!self.conditon_codes.is_parity_set())?,
             0xe1 => self.pop_h()?,
-            0xe2 => self.jump_on_parity(!self.conditon_codes.is_parity_set())?,
-            0xe3 => self.xthl(),
-            0xe4 => self.call_on_parity(!self.conditon_codes.is_parity_set())?,
+            0xe2 => taken = self.jump_on_parity(!self.conditon_codes.is_parity_set())?,
+            0xe3 => self.xthl()?,
+            0xe4 => taken = self.call_on_parity(!self.conditon_codes.is_parity_set())?,
             0xe5 => self.push_h()?,
             0xe6 => self.ani()?,
             0xe7 => self.rst(4)?,
-            0xe8 => self.ret_on_parity(self.conditon_codes.is_parity_set())?,
+            0xe8 => taken = self.ret_on_parity(self.conditon_codes.is_parity_set())?,
             0xe9 => self.pc = u16::from_le_bytes([self.reg_l, self.reg_h]),
-            0xea => self.jump_on_parity(self.conditon_codes.is_parity_set())?,
+            0xea => taken = self.jump_on_parity(self.conditon_codes.is_parity_set())?,
             0xeb => self.xchg(),
-            0xec => self.call_on_parity(self.conditon_codes.is_parity_set())?,
+            0xec => taken = self.call_on_parity(self.conditon_codes.is_parity_set())?,
             0xee => self.xri()?,
             0xef => self.rst(5)?,
-            0xf0 => self.ret_on_sign(!self.conditon_codes.is_sign_set())?,
+            0xf0 => taken = self.ret_on_sign(!self.conditon_codes.is_sign_set())?,
             0xf1 => self.pop_psw()?,
-            0xf2 => self.jump_on_sign(!self.conditon_codes.is_sign_set())?,
+            0xf2 => taken = self.jump_on_sign(!self.conditon_codes.is_sign_set())?,
             0xf3 => self.interrupt_enabled = false,
-            0xf4 => self.call_on_sign(!self.conditon_codes.is_sign_set())?,
+            0xf4 => taken = self.call_on_sign(!self.conditon_codes.is_sign_set())?,
             0xf5 => self.push_psw()?,
             0xf6 => self.ori()?,
             0xf7 => self.rst(6)?,
-            0xf8 => self.ret_on_sign(self.conditon_codes.is_sign_set())?,
+            0xf8 => taken = self.ret_on_sign(self.conditon_codes.is_sign_set())?,
             0xf9 => self.sp = u16::from_le_bytes([self.reg_l, self.reg_h]),
-            0xfa => self.jump_on_sign(self.conditon_codes.is_sign_set())?,
-            0xfb => self.interrupt_enabled = true,
-            0xfc => self.call_on_sign(self.conditon_codes.is_sign_set())?,
+            0xfa => taken = self.jump_on_sign(self.conditon_codes.is_sign_set())?,
+            0xfb => {
+                self.interrupt_enabled = true;
+                self.interrupt_delayed = true
+            }
+            0xfc => taken = self.call_on_sign(self.conditon_codes.is_sign_set())?,
             0xfe => self.cpi()?,
             0xff => self.rst(7)?,
         }
-        Ok(CLOCK_CYCLES[opcode as usize] as u64)
+        Ok(self.cycles_of(opcode, taken))
+    }
+
+    fn cycles_of(&self, opcode: u8, taken: bool) -> u64 {
+        let cycles = match (self.variant, taken) {
+            (Variant::I8080, true) => CLOCK_CYCLES,
+            (Variant::I8080, false) => CLOCK_CYCLES_NOT_TAKEN,
+            (Variant::I8085, true) => CLOCK_CYCLES_8085,
+            (Variant::I8085, false) => CLOCK_CYCLES_NOT_TAKEN_8085,
+        };
+        cycles[opcode as usize] as u64
     }
 
     fn load_stack_pointer_from_operand(&mut self) -> Result<()> {
         self.sp = u16::from_le_bytes(self.load_d16_operand()?);
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
@@ -841,30 +1293,28 @@ impl Cpu8080 {
 
     fn shld(&mut self) -> Result<()> {
         let address = u16::from_le_bytes(self.load_d16_operand()?);
-        self.store_to_ram(address.into(), self.reg_l)?;
-        self.store_to_ram((address + 1).into(), self.reg_h)?;
-        self.pc += 2;
+        self.store_to_memory(address, self.reg_l)?;
+        self.store_to_memory(address.wrapping_add(1), self.reg_h)?;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn lhld(&mut self) -> Result<()> {
         let address = u16::from_le_bytes(self.load_d16_operand()?);
-        let lo = self.load_byte_from_memory(address.into())?;
-        let hi = self.load_byte_from_memory((address + 1).into())?;
+        let lo = self.load_byte_from_memory(address)?;
+        let hi = self.load_byte_from_memory(address.wrapping_add(1))?;
         (self.reg_l, self.reg_h) = (lo, hi);
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
-    fn xthl(&mut self) {
-        mem::swap(
-            &mut self.ram[self.sp as usize - self.rom.len()],
-            &mut self.reg_l,
-        );
-        mem::swap(
-            &mut self.ram[(self.sp + 1) as usize - self.rom.len()],
-            &mut self.reg_h,
-        );
+    fn xthl(&mut self) -> Result<()> {
+        let lo = self.load_byte_from_memory(self.sp)?;
+        let hi = self.load_byte_from_memory(self.sp.wrapping_add(1))?;
+        self.store_to_memory(self.sp, self.reg_l)?;
+        self.store_to_memory(self.sp.wrapping_add(1), self.reg_h)?;
+        (self.reg_l, self.reg_h) = (lo, hi);
+        Ok(())
     }
 
     fn xchg(&mut self) {
@@ -874,15 +1324,15 @@ impl Cpu8080 {
 
     fn sta(&mut self) -> Result<()> {
         let address = u16::from_le_bytes(self.load_d16_operand()?);
-        self.store_to_ram(address.into(), self.reg_a)?;
-        self.pc += 2;
+        self.store_to_memory(address, self.reg_a)?;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn lda(&mut self) -> Result<()> {
         let address = u16::from_le_bytes(self.load_d16_operand()?);
-        self.reg_a = self.load_byte_from_memory(address.into())?;
-        self.pc += 2;
+        self.reg_a = self.load_byte_from_memory(address)?;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
@@ -893,150 +1343,410 @@ impl Cpu8080 {
     ];
 
     fn pop_psw(&mut self) -> Result<()> {
-        let lo = self.load_byte_from_memory(self.sp.into())?;
-        let hi = self.load_byte_from_memory((self.sp + 1).into())?;
-        (*self.conditon_codes.deref_mut(), self.reg_a) = (lo, hi);
-        self.sp += 2;
+        let lo = self.load_byte_from_memory(self.sp)?;
+        let hi = self.load_byte_from_memory(self.sp.wrapping_add(1))?;
+        self.set_psw(lo);
+        self.reg_a = hi;
+        self.sp = self.sp.wrapping_add(2);
         Ok(())
     }
 
     fn push_psw(&mut self) -> Result<()> {
-        self.store_to_ram((self.sp - 1).into(), self.reg_a)?;
-        self.store_to_ram((self.sp - 2).into(), *self.conditon_codes.deref())?;
-        self.sp -= 2;
+        self.store_to_memory(self.sp.wrapping_sub(1), self.reg_a)?;
+        self.store_to_memory(self.sp.wrapping_sub(2), self.psw())?;
+        self.sp = self.sp.wrapping_sub(2);
         Ok(())
     }
 
     fn call(&mut self) -> Result<()> {
-        let pc_in_bytes = (self.pc + 2).to_be_bytes();
-        self.store_to_ram((self.sp - 1).into(), pc_in_bytes[0])?;
-        self.store_to_ram((self.sp - 2).into(), pc_in_bytes[1])?;
-        self.sp -= 2;
-        #[cfg(feature = "cpu_diag")]
-        let old_pc = self.pc - 1;
+        let pc_in_bytes = self.pc.wrapping_add(2).to_be_bytes();
+        self.store_to_memory(self.sp.wrapping_sub(1), pc_in_bytes[0])?;
+        self.store_to_memory(self.sp.wrapping_sub(2), pc_in_bytes[1])?;
+        self.sp = self.sp.wrapping_sub(2);
         self.pc = u16::from_le_bytes(self.load_d16_operand()?);
-        #[cfg(feature = "cpu_diag")]
-        println!(
-            "call into {:#06x} from {:#06x}, sp = {:#06x}",
-            self.pc, old_pc, self.sp
-        );
-
         Ok(())
     }
 
-    #[cfg(feature = "cpu_diag")]
-    fn call_bdos(&mut self) -> Result<()> {
-        let msg_addr = (u16::from_le_bytes([self.reg_e, self.reg_d]) + 3) as usize; // skipping 0CH,0DH,0AH
-        assert_eq!(msg_addr, 0x0178);
-        let msg: Vec<u8> = self
-            .rom
-            .iter()
-            .skip(msg_addr)
-            .take_while(|&&c| c as char != '$')
-            .map(|c| c.to_owned())
-            .collect();
-        println!("{}", String::from_utf8_lossy(&msg));
-        self.ret()?;
+    fn push_pc(&mut self) -> Result<()> {
+        let pc_in_bytes = self.pc.to_be_bytes();
+        self.store_to_memory(self.sp.wrapping_sub(1), pc_in_bytes[0])?;
+        self.store_to_memory(self.sp.wrapping_sub(2), pc_in_bytes[1])?;
+        self.sp = self.sp.wrapping_sub(2);
         Ok(())
     }
 
     fn rst(&mut self, rst_no: u8) -> Result<()> {
         match rst_no {
-            1..=7 => {
-                let pc_in_bytes = self.pc.to_be_bytes();
-                self.store_to_ram((self.sp - 1).into(), pc_in_bytes[0])?;
-                self.store_to_ram((self.sp - 2).into(), pc_in_bytes[1])?;
-                self.sp -= 2;
-                #[cfg(feature = "cpu_diag")]
-                let old_pc = self.pc;
+            0..=7 => {
+                self.push_pc()?;
                 self.pc = rst_no as u16 * 8;
-                #[cfg(feature = "cpu_diag")]
-                println!("Interrupted to {:#06x} from {:#06x}", self.pc, old_pc);
             }
-            _ => panic!("unsupported IRQ {rst_no}"),
+            _ => return Err(EmulatorErrors::UnsupportedInterrupt(rst_no)),
         }
         Ok(())
     }
 
     fn output(&mut self) -> Result<()> {
-        #[cfg(not(feature = "cpu_diag"))]
-        {
-            let dev_no = self.load_d8_operand()?;
-            (self.io_callbacks.output)(self.io_object, dev_no, self.reg_a);
-        }
+        let dev_no = self.load_d8_operand()?;
+        self.io.output(dev_no, self.reg_a);
         Ok(())
     }
 
     fn input(&mut self) -> Result<()> {
-        #[cfg(not(feature = "cpu_diag"))]
-        {
-            let dev_no = self.load_d8_operand()?;
-            self.reg_a = (self.io_callbacks.input)(self.io_object, dev_no);
-        }
+        let dev_no = self.load_d8_operand()?;
+        self.reg_a = self.io.input(dev_no);
         Ok(())
     }
 
+    /// Both corrections are decided on the original accumulator and
+    /// added at once, the carry is only ever set, never cleared
     fn daa(&mut self) {
-        if (self.reg_a & 0xf) > 0x9 || self.conditon_codes.is_aux_carry_set() {
-            let aux_carry = self.reg_a as u16 + 6;
-            self.conditon_codes.set_aux_carry((aux_carry & 0xf) < 0x6);
-            self.reg_a = aux_carry as u8;
+        let (lsb, msb) = (self.reg_a & 0xf, self.reg_a >> 4);
+        let mut carry = self.conditon_codes.is_carry_set();
+        let mut correction = 0;
+        if lsb > 9 || self.conditon_codes.is_aux_carry_set() {
+            correction |= 0x06
         }
-        if (self.reg_a & 0xf0) > 0x90 || self.conditon_codes.is_carry_set() {
-            let result = self.reg_a as u16 + (6u8 << 4) as u16;
-            if result > u8::MAX.into() {
-                self.conditon_codes.set_carry(true)
-            }
-            self.reg_a = result as u8;
+        if msb > 9 || msb == 9 && lsb > 9 || carry {
+            correction |= 0x60;
+            carry = true
         }
-        self.conditon_codes.set_zero(self.reg_a == 0);
-        self.conditon_codes.set_sign(self.reg_a >= 0x80);
-        self.conditon_codes
-            .set_parity(self.reg_a.count_ones() % 2 == 0);
+        self.reg_a = self.set_condition_bits(self.reg_a, correction, false) as u8;
+        self.conditon_codes.set_carry(carry);
     }
 
     fn ret(&mut self) -> Result<()> {
-        let addr_lo = self.load_byte_from_memory(self.sp.into())?;
-        let addr_hi = self.load_byte_from_memory((self.sp + 1).into())?;
+        let addr_lo = self.load_byte_from_memory(self.sp)?;
+        let addr_hi = self.load_byte_from_memory(self.sp.wrapping_add(1))?;
         self.pc = u16::from_le_bytes([addr_lo, addr_hi]);
-        self.sp += 2;
-        #[cfg(feature = "cpu_diag")]
-        println!("Return back to {:#06x}, sp = {:#06x}", self.pc, self.sp);
+        self.sp = self.sp.wrapping_add(2);
         Ok(())
     }
 
     /// get operand parts in (lo, hi)
     fn load_d16_operand(&self) -> Result<[u8; 2]> {
-        Ok([
-            self.load_byte_from_memory((self.pc).into())?,
-            self.load_byte_from_memory((self.pc + 1).into())?,
-        ])
+        Ok([self.fetch(self.pc)?, self.fetch(self.pc.wrapping_add(1))?])
     }
 
     fn load_d8_operand(&mut self) -> Result<u8> {
-        let value = self.load_byte_from_memory((self.pc).into())?;
-        self.pc += 1;
+        let value = self.fetch(self.pc)?;
+        self.pc = self.pc.wrapping_add(1);
         Ok(value)
     }
 
     fn jmp(&mut self) -> Result<()> {
-        #[cfg(feature = "cpu_diag")]
-        let old_pc = self.pc - 1;
         self.pc = u16::from_le_bytes(self.load_d16_operand()?);
-        #[cfg(feature = "cpu_diag")]
-        println!("Jump from {:#06x} to {:#06x}", old_pc, self.pc);
         Ok(())
     }
 }
 
 #[cfg(test)]
-#[cfg(feature = "cpu_diag")]
 mod tests {
+    use std::{cell::RefCell, io::Write, rc::Rc, time::Duration};
+
     use super::*;
+    use crate::cpm::load_com;
+
+    #[derive(Clone, Default)]
+    struct Console(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Console {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cpu_diagnosis() {
+        let image = include_bytes!("../diagnosis_program/cpudiag");
+        let memory = load_com(&image[TPA_START as usize..]).unwrap();
+        let (mut cpu, sender) = Cpu8080::with_memory(memory, ());
+        let console = Console::default();
+        cpu.enable_cpm(Bdos::new(console.clone()));
+        sender.send(Message::Suspend).unwrap();
+        cpu.run().unwrap();
+        assert!(cpu.has_exited());
+        let output = String::from_utf8_lossy(&console.0.borrow()).into_owned();
+        assert!(output.contains("CPU IS OPERATIONAL"), "{output}");
+    }
+
+    #[test]
+    fn step_and_bounded_run() {
+        // MVI A,05H; ADI 03H; CNZ 0009H; HLT; RET
+        let rom = vec![0x3e, 0x05, 0xc6, 0x03, 0xc4, 0x09, 0x00, 0x76, 0x00, 0xc9];
+        let (mut cpu, _) = Cpu8080::new(rom, vec![0; 0x10], ());
+        cpu.sp = 0x1a;
+        let info = cpu.step().unwrap();
+        assert_eq!(
+            info,
+            StepInfo {
+                opcode: 0x3e,
+                cycles: 7,
+                pc: 0x02
+            }
+        );
+        assert_eq!(cpu.run_for_cycles(1).unwrap(), 7);
+        assert_eq!(cpu.reg_a, 0x08);
+        let info = cpu.step().unwrap();
+        assert_eq!((info.opcode, info.cycles, info.pc), (0xc4, 17, 0x09));
+        assert_eq!(cpu.run_until(0x07).unwrap(), 10);
+        // CZ and RZ not taken, RNZ taken
+        cpu.pc = 0x05;
+        assert_eq!(cpu.execute(0xcc).unwrap(), 11);
+        assert_eq!(cpu.pc, 0x07);
+        assert_eq!(cpu.execute(0xc8).unwrap(), 5);
+        cpu.sp = 0x18;
+        assert_eq!(cpu.execute(0xc0).unwrap(), 11);
+        assert_eq!(cpu.pc, 0x07);
+        assert_eq!(cpu.sp, 0x1a);
+    }
+
+    #[test]
+    fn host_driven_pacing() {
+        // JMP 0000H, 10 cycles per slice of 100 cycles at 1 MHz
+        let (mut cpu, sender) = Cpu8080::new(vec![0xc3, 0x00, 0x00], vec![], ());
+        let slices = Rc::new(RefCell::new(vec![]));
+        let paced = slices.clone();
+        let shutdown = sender.clone();
+        cpu.set_clock(
+            Clock::new(1_000_000)
+                .slice_cycles(100)
+                .pacer(move |emulated, _| {
+                    paced.borrow_mut().push(emulated);
+                    if paced.borrow().len() == 3 {
+                        shutdown.send(Message::Shutdown).unwrap();
+                    }
+                }),
+        );
+        sender.send(Message::Suspend).unwrap();
+        cpu.run().unwrap();
+        assert_eq!(*slices.borrow(), vec![Duration::from_micros(100); 3]);
+    }
+
+    #[test]
+    fn unusable_messages_are_dropped() {
+        // JMP 0000H
+        let (mut cpu, sender) = Cpu8080::new(vec![0xc3, 0x00, 0x00], vec![], ());
+        cpu.set_clock(Clock::new(0));
+        sender.send(Message::Suspend).unwrap();
+        sender
+            .send(Message::Interrupt {
+                irq_no: 8,
+                allow_nested_interrupt: false,
+            })
+            .unwrap();
+        sender
+            .send(Message::SetInterruptPin {
+                pin: InterruptPin::Trap,
+                level: true,
+            })
+            .unwrap();
+        sender.send(Message::Shutdown).unwrap();
+        cpu.run().unwrap();
+        assert!(cpu.cycles() > 0);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let (mut cpu, _) = Cpu8080::new(vec![0; 0], vec![0; 0], ());
+        let mut state = CpuState {
+            reg_a: 0x12,
+            sp: 0x2400,
+            pc: 0x0100,
+            interrupt_enabled: true,
+            ..Default::default()
+        };
+        state.set_bc(0x3456);
+        state.set_hl(0x789a);
+        let mut condition_codes = ConditionCodes::default();
+        condition_codes.set_carry(true);
+        state.set_condition_codes(condition_codes);
+        cpu.set_state(&state);
+        assert_eq!(
+            (cpu.reg_b, cpu.reg_c, cpu.reg_h, cpu.reg_l),
+            (0x34, 0x56, 0x78, 0x9a)
+        );
+        assert!(cpu.conditon_codes.is_carry_set());
+        assert_eq!(cpu.state(), state);
+    }
+
+    #[test]
+    fn interrupt_resumes_after_hlt() {
+        // EI; HLT; NOP
+        let (mut cpu, _) = Cpu8080::new(vec![0xfb, 0x76, 0x00], vec![0; 0x10], ());
+        cpu.sp = 0x13;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.is_halted());
+        let info = cpu.step().unwrap();
+        assert_eq!((info.opcode, info.pc), (0x76, 0x02));
+        assert!(cpu.is_halted());
+
+        assert_eq!(cpu.interrupt(&[0xcf], true).unwrap(), 11);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.pc, 0x08);
+        // returning from the handler continues after HLT
+        assert_eq!(cpu.get_ram()[0x0e..0x10], [0x02, 0x00]);
+    }
+
+    #[test]
+    fn halted_with_interrupts_disabled() {
+        let (mut cpu, _) = Cpu8080::new(vec![0x76], vec![0; 0x10], ());
+        cpu.sp = 0x11;
+        let halts = Rc::new(RefCell::new(0));
+        let counter = halts.clone();
+        cpu.set_halt_handler(move || *counter.borrow_mut() += 1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.interrupt(&[0xcf], false).unwrap(), 0);
+        assert!(cpu.is_halted());
+        assert!(cpu.state().halted);
+        cpu.step().unwrap();
+        assert_eq!(*halts.borrow(), 1);
+        // stops instead of waiting forever
+        assert_eq!(cpu.run_until(0x1234).unwrap(), 0);
+
+        // devices keep their timeline until a message arrives
+        let (mut cpu, sender) = Cpu8080::new(vec![0x76], vec![0; 0x10], ());
+        let updates = Rc::new(RefCell::new(0));
+        let device = updates.clone();
+        let shutdown = sender.clone();
+        cpu.schedule(
+            100,
+            Some(100),
+            EventAction::Device(Box::new(move |_| {
+                *device.borrow_mut() += 1;
+                if *device.borrow() == 5 {
+                    shutdown.send(Message::Shutdown).unwrap()
+                }
+            })),
+        )
+        .unwrap();
+        sender.send(Message::Suspend).unwrap();
+        cpu.run().unwrap();
+        assert_eq!(*updates.borrow(), 5);
+        assert!(cpu.is_halted());
+
+        // nothing can wake it up once the sender is gone
+        let (mut cpu, sender) = Cpu8080::new(vec![0x76], vec![0; 0x10], ());
+        sender.send(Message::Suspend).unwrap();
+        drop(sender);
+        assert!(matches!(cpu.run(), Err(EmulatorErrors::Disconnected)));
+        assert!(cpu.is_halted());
+    }
+
+    #[test]
+    fn interrupt_instructions() {
+        // EI; RST 0 at 0x0000 would loop, so start at 0x0001
+        let (mut cpu, _) = Cpu8080::new(vec![0xfb, 0xfb, 0xc7], vec![0; 0x10], ());
+        cpu.sp = 0x13;
+        cpu.pc = 1;
+        cpu.step().unwrap();
+        assert_eq!(cpu.step().unwrap().pc, 0x0000);
+        assert_eq!(cpu.get_ram()[0x0e..0x10], [0x03, 0x00]);
+
+        // the device places CALL 1234H on the bus
+        cpu.interrupt_enabled = true;
+        assert_eq!(cpu.interrupt(&[0xcd, 0x34, 0x12], false).unwrap(), 17);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.sp, 0x0f);
+        assert_eq!(cpu.get_ram()[0x0c..0x0e], [0x00, 0x00]);
+        assert!(!cpu.interrupt_enabled);
+
+        // non control flow instructions leave the PC where it was
+        cpu.interrupt_enabled = true;
+        assert_eq!(cpu.interrupt(&[0x3e, 0x42], false).unwrap(), 7);
+        assert_eq!((cpu.pc, cpu.reg_a), (0x1234, 0x42));
+
+        assert!(cpu.interrupt(&[0xcd, 0x34], false).is_err());
+        assert!(rst_instruction(8).is_err());
+        assert_eq!(rst_instruction(7).unwrap(), 0xff);
+    }
+
+    #[test]
+    fn undocumented_aliases() {
+        // *JMP 0006H; NOP; NOP; *CALL 000AH; NOP; *RET
+        let rom = vec![
+            0xcb, 0x06, 0x00, 0x00, 0x00, 0x00, 0xdd, 0x0a, 0x00, 0x00, 0xd9,
+        ];
+        let (mut cpu, _) = Cpu8080::new(rom, vec![0; 0x10], ());
+        cpu.sp = 0x1b;
+        assert_eq!(cpu.step().unwrap().pc, 0x06);
+        assert_eq!(cpu.step().unwrap().pc, 0x0a);
+        assert_eq!(
+            cpu.step().unwrap(),
+            StepInfo {
+                opcode: 0xd9,
+                cycles: 10,
+                pc: 0x09
+            }
+        );
+
+        // every CALL alias pushes the return address
+        for opcode in [0xdd, 0xed, 0xfd] {
+            let (mut cpu, _) = Cpu8080::new(vec![opcode, 0x34, 0x12], vec![0; 0x10], ());
+            cpu.sp = 0x13;
+            let info = cpu.step().unwrap();
+            assert!(is_call(opcode, Variant::I8080));
+            assert!(!is_call(opcode, Variant::I8085));
+            assert_eq!((info.pc, info.cycles), (0x1234, 17));
+            assert_eq!(cpu.get_ram()[0x0e..0x10], [0x03, 0x00]);
+        }
+    }
+
+    #[test]
+    fn scheduled_events() {
+        let rom = crate::assemble(
+            "
+        EI
+IDLE:   JMP     IDLE
+        ORG     8       ; RST 1
+        INR     B
+        EI
+        RET
+        ORG     10H     ; RST 2
+        INR     C
+        EI
+        RET
+",
+        )
+        .unwrap();
+        let (mut cpu, _) = Cpu8080::new(rom, vec![0; 0x10], ());
+        cpu.sp = 0x23;
+        let rst = |irq_no| EventAction::Interrupt {
+            instruction: [rst_instruction(irq_no).unwrap(), 0, 0],
+            allow_nested_interrupt: false,
+        };
+        let mid_screen = cpu.schedule(100, Some(200), rst(1)).unwrap();
+        cpu.schedule(200, Some(200), rst(2)).unwrap();
+        let fired = Rc::new(RefCell::new(vec![]));
+        let device = fired.clone();
+        cpu.schedule(
+            150,
+            None,
+            EventAction::Device(Box::new(move |_| device.borrow_mut().push(()))),
+        )
+        .unwrap();
+
+        let used = cpu.run_for_cycles(1000).unwrap();
+        assert_eq!(cpu.cycles(), used);
+        // the last RST 2 has just been taken, its handler is yet to run
+        assert_eq!((cpu.reg_b, cpu.reg_c, cpu.pc), (5, 4, 0x10));
+        assert_eq!(fired.borrow().len(), 1);
+
+        assert!(cpu.cancel(mid_screen));
+        assert!(!cpu.cancel(mid_screen));
+        cpu.run_for_cycles(1000).unwrap();
+        assert_eq!((cpu.reg_b, cpu.reg_c), (5, 9));
+        assert!(cpu.schedule(0, Some(0), rst(1)).is_err());
+    }
 
     #[test]
     fn cpu_opcode_tests() {
-        let mut cpu = Cpu8080::cpudiag_new(vec![0; 0], vec![0; 0]);
+        let (mut cpu, _) = Cpu8080::new(vec![0; 0], vec![0; 0], ());
 
         // test RAL & RAR
         cpu.reg_a = 0xb5;
@@ -1080,4 +1790,229 @@ mod tests {
         assert!(cpu.conditon_codes.is_carry_set());
         assert!(!cpu.conditon_codes.is_aux_carry_set());
     }
+
+    #[test]
+    fn execute_from_ram() {
+        // copy ROUTINE into RAM and call it there, it runs from nowhere else
+        let rom = crate::assemble(
+            "
+RAMCODE EQU     100H
+        LXI     SP,200H
+        LXI     H,RAMCODE
+        LXI     D,ROUTINE
+        MVI     B,LENGTH
+COPY:   LDAX    D
+        MOV     M,A
+        INX     D
+        INX     H
+        DCR     B
+        JNZ     COPY
+        CALL    RAMCODE
+        HLT
+ROUTINE:
+        MVI     C,42H
+        RET
+LENGTH  EQU     $ - ROUTINE
+",
+        )
+        .unwrap();
+        let ram = vec![0; 0x200 - rom.len()];
+        let (mut cpu, _) = Cpu8080::new(rom, ram, ());
+        let ram_start = 0x200 - cpu.get_ram().len() as u16;
+        let mut ram_pcs = 0;
+        while !cpu.is_halted() {
+            if cpu.pc >= ram_start {
+                ram_pcs += 1
+            }
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.reg_c, 0x42);
+        assert_eq!(ram_pcs, 2);
+
+        // running off the end of the memory is an error, not a silent stop
+        cpu.pc = 0x1ff;
+        cpu.halted = false;
+        cpu.step().unwrap();
+        assert!(cpu.step().is_err());
+    }
+
+    #[test]
+    fn address_wraparound() {
+        let (mut cpu, _) = Cpu8080::new(vec![], vec![0; 0x10000], ());
+        let poke = |cpu: &mut Cpu8080<RomRam, ()>, addr: u16, bytes: &[u8]| {
+            for (offset, &byte) in (0..).zip(bytes) {
+                cpu.store_to_memory(addr.wrapping_add(offset), byte)
+                    .unwrap()
+            }
+        };
+
+        // the stack at 0x0000 grows down from 0xffff
+        (cpu.sp, cpu.reg_b, cpu.reg_c) = (0x0000, 0x12, 0x34);
+        cpu.push_b().unwrap();
+        assert_eq!(cpu.sp, 0xfffe);
+        assert_eq!(cpu.get_ram()[0xfffe..], [0x34, 0x12]);
+        cpu.pop_d().unwrap();
+        assert_eq!((cpu.sp, cpu.reg_d, cpu.reg_e), (0x0000, 0x12, 0x34));
+        cpu.sp = 0xffff;
+        cpu.xthl().unwrap();
+        assert_eq!((cpu.reg_h, cpu.reg_l), (0x00, 0x12));
+        cpu.execute(0x33).unwrap(); // INX SP
+        assert_eq!(cpu.sp, 0x0000);
+        cpu.execute(0x3b).unwrap(); // DCX SP
+        assert_eq!(cpu.sp, 0xffff);
+
+        // MVI A at 0xffff takes its operand from 0x0000,
+        // then JMP at 0xfffe from 0xffff and 0x0000, the
+        // tracer reads the bytes around the end too
+        cpu.set_tracer(|_: &TraceRecord| ());
+        poke(&mut cpu, 0xffff, &[0x3e, 0x99]);
+        cpu.pc = 0xffff;
+        cpu.step().unwrap();
+        assert_eq!((cpu.reg_a, cpu.pc), (0x99, 0x0001));
+        poke(&mut cpu, 0xfffe, &[0xc3, 0x34, 0x12]);
+        cpu.pc = 0xfffe;
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x1234);
+
+        // a word at 0xffff has its high byte at 0x0000
+        (cpu.reg_h, cpu.reg_l) = (0xab, 0xcd);
+        poke(&mut cpu, 0x0100, &[0x22, 0xff, 0xff, 0x2a, 0xff, 0xff]);
+        cpu.pc = 0x0100;
+        cpu.step().unwrap(); // SHLD 0FFFFH
+        assert_eq!((cpu.get_ram()[0xffff], cpu.get_ram()[0]), (0xcd, 0xab));
+        (cpu.reg_h, cpu.reg_l) = (0, 0);
+        cpu.step().unwrap(); // LHLD 0FFFFH
+        assert_eq!((cpu.reg_h, cpu.reg_l), (0xab, 0xcd));
+    }
+
+    #[test]
+    fn interrupt_line() {
+        let rom = crate::assemble(
+            "
+        DI
+        EI
+        NOP
+        NOP
+        EI
+        HLT
+        HLT
+        ORG     10H     ; RST 2
+        INR     B
+        RET
+",
+        )
+        .unwrap();
+        let (mut cpu, _) = Cpu8080::new(rom, vec![0; 0x10], ());
+        cpu.sp = 0x22;
+        let rst_2 = [rst_instruction(2).unwrap()];
+
+        // held while disabled, INTE left alone
+        cpu.step().unwrap();
+        assert_eq!(cpu.interrupt(&rst_2, true).unwrap(), 0);
+        assert!(cpu.is_interrupt_pending() && !cpu.interrupt_enabled);
+
+        // not before the instruction following EI is done
+        cpu.step().unwrap();
+        assert!(cpu.is_interrupt_pending() && cpu.pc == 0x0002);
+        let info = cpu.step().unwrap();
+        assert_eq!((info.cycles, cpu.pc), (4 + 11, 0x0010));
+        assert!(!cpu.is_interrupt_pending() && cpu.interrupt_enabled);
+        cpu.run_until(0x0003).unwrap();
+
+        // taken right away when enabled, INTE cleared on acknowledge
+        assert_eq!(cpu.interrupt(&rst_2, false).unwrap(), 11);
+        assert!(!cpu.interrupt_enabled);
+        cpu.run_until(0x0003).unwrap();
+        assert_eq!(cpu.reg_b, 2);
+
+        // EI; HLT is woken up by an interrupt requested in between
+        cpu.run_until(0x0005).unwrap();
+        assert_eq!(cpu.interrupt(&rst_2, false).unwrap(), 0);
+        cpu.step().unwrap();
+        assert_eq!((cpu.halted, cpu.pc), (false, 0x0010));
+        cpu.run_until(0x0006).unwrap();
+        assert_eq!(cpu.reg_b, 3);
+
+        // a CPU halted with interrupts disabled stays so
+        cpu.step().unwrap();
+        assert_eq!(cpu.interrupt(&rst_2, false).unwrap(), 0);
+        cpu.step().unwrap();
+        assert!(cpu.halted && cpu.is_interrupt_pending());
+    }
+
+    #[test]
+    fn psw_layout() {
+        let (mut cpu, _) = Cpu8080::new(vec![0; 0], vec![0; 4], ());
+        cpu.sp = 4;
+        cpu.reg_a = 0x42;
+        cpu.conditon_codes.set_sign(true);
+        cpu.conditon_codes.set_carry(true);
+        cpu.push_psw().unwrap();
+        assert_eq!(cpu.get_ram()[2..], [0x83, 0x42]);
+        assert_eq!(cpu.state().flags, 0x83);
+
+        // the unused bits are dropped
+        cpu.store_to_memory(2, 0xff).unwrap();
+        cpu.pop_psw().unwrap();
+        assert_eq!(cpu.conditon_codes.to_psw(), 0xd7);
+        assert!(cpu.conditon_codes.is_zero_set() && cpu.conditon_codes.is_parity_set());
+        assert!(cpu.conditon_codes.is_aux_carry_set());
+
+        let mut state = cpu.state();
+        state.flags = 0x40 | 0x02;
+        cpu.set_state(&state);
+        assert!(cpu.conditon_codes.is_zero_set() && !cpu.conditon_codes.is_carry_set());
+    }
+
+    #[test]
+    fn aux_carry_and_borrow() {
+        let (mut cpu, _) = Cpu8080::new(vec![0; 0], vec![0; 0], ());
+        let flags = |cpu: &Cpu8080<RomRam, ()>| {
+            (
+                cpu.conditon_codes.is_carry_set(),
+                cpu.conditon_codes.is_aux_carry_set(),
+            )
+        };
+
+        // the incoming carry counts for AC
+        cpu.reg_a = 0x01;
+        cpu.conditon_codes.set_carry(true);
+        cpu.adc(0x0f);
+        assert_eq!((cpu.reg_a, flags(&cpu)), (0x11, (false, true)));
+
+        // AC is set when no borrow comes out of the low nibble
+        cpu.reg_a = 0x10;
+        cpu.sub(0x01);
+        assert_eq!((cpu.reg_a, flags(&cpu)), (0x0f, (false, false)));
+        cpu.reg_a = 0x05;
+        cpu.sub(0x01);
+        assert_eq!((cpu.reg_a, flags(&cpu)), (0x04, (false, true)));
+
+        // the incoming borrow counts for the outgoing one
+        cpu.reg_a = 0x00;
+        cpu.conditon_codes.set_carry(true);
+        cpu.sbb(0x00);
+        assert_eq!((cpu.reg_a, flags(&cpu)), (0xff, (true, false)));
+
+        cpu.reg_a = 0x10;
+        cpu.cmp(0x20);
+        assert_eq!((cpu.reg_a, flags(&cpu)), (0x10, (true, true)));
+
+        cpu.reg_a = 0x08;
+        cpu.and(0x00);
+        assert_eq!((cpu.reg_a, flags(&cpu)), (0x00, (false, true)));
+
+        cpu.reg_a = 0x10;
+        cpu.drc_a();
+        assert_eq!((cpu.reg_a, flags(&cpu).1), (0x0f, false));
+        cpu.inr_a();
+        assert_eq!((cpu.reg_a, flags(&cpu).1), (0x10, true));
+
+        cpu.reg_a = 0x9a;
+        cpu.conditon_codes.set_carry(false);
+        cpu.conditon_codes.set_aux_carry(false);
+        cpu.daa();
+        assert_eq!((cpu.reg_a, flags(&cpu)), (0x00, (true, true)));
+        assert!(cpu.conditon_codes.is_zero_set());
+    }
 }
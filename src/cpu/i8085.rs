@@ -0,0 +1,496 @@
+use super::{Cpu8080, Variant};
+use crate::{condition_codes::ConditionCodes, EmulatorErrors, MemoryBus, PortIo, Result};
+
+/// The interrupt inputs the 8085 adds to INTR, from the highest priority
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptPin {
+    /// Not maskable, taken on a rising edge while the level is held, RST 4.5
+    Trap,
+    /// Latched on a rising edge, RST 7.5
+    Rst75,
+    /// Level sensitive, RST 6.5
+    Rst65,
+    /// Level sensitive, RST 5.5
+    Rst55,
+}
+
+impl InterruptPin {
+    fn vector(self) -> u16 {
+        match self {
+            InterruptPin::Trap => 0x24,
+            InterruptPin::Rst75 => 0x3c,
+            InterruptPin::Rst65 => 0x34,
+            InterruptPin::Rst55 => 0x2c,
+        }
+    }
+}
+
+/// What the 8085 keeps on top of the 8080 registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Pins8085 {
+    /// Levels of `InterruptPin`s in their order
+    levels: [bool; 4],
+    trap_latched: bool,
+    rst75_latched: bool,
+    /// M7.5, M6.5 and M5.5 as set by SIM
+    masks: u8,
+    /// INTE before TRAP was taken, for the next RIM
+    inte_before_trap: Option<bool>,
+    sid: bool,
+    sod: bool,
+}
+
+impl Default for Pins8085 {
+    /// RESET masks RST 7.5, 6.5 and 5.5
+    fn default() -> Self {
+        Pins8085 {
+            levels: [false; 4],
+            trap_latched: false,
+            rst75_latched: false,
+            masks: 0x07,
+            inte_before_trap: None,
+            sid: false,
+            sod: false,
+        }
+    }
+}
+
+impl Pins8085 {
+    /// levels(1, a bit per `InterruptPin`) latches(1, TRAP then RST 7.5)
+    /// masks(1) inte_before_trap(1, 0 if none) sid(1) sod(1)
+    pub(crate) fn to_bytes(self) -> [u8; 6] {
+        let levels = (0..4).fold(0, |bits, pin| bits | (self.levels[pin] as u8) << pin);
+        [
+            levels,
+            self.trap_latched as u8 | (self.rst75_latched as u8) << 1,
+            self.masks,
+            self.inte_before_trap.map_or(0, |inte| 1 + inte as u8),
+            self.sid as u8,
+            self.sod as u8,
+        ]
+    }
+
+    pub(crate) fn from_bytes(
+        [levels, latches, masks, inte_before_trap, sid, sod]: [u8; 6],
+    ) -> Self {
+        Pins8085 {
+            levels: std::array::from_fn(|pin| levels & 1 << pin != 0),
+            trap_latched: latches & 0x01 != 0,
+            rst75_latched: latches & 0x02 != 0,
+            masks: masks & 0x07,
+            inte_before_trap: (inte_before_trap != 0).then_some(inte_before_trap == 2),
+            sid: sid != 0,
+            sod: sod != 0,
+        }
+    }
+}
+
+impl<M: MemoryBus, P: PortIo> Cpu8080<M, P> {
+    fn check_8085(&self) -> Result<()> {
+        match self.variant {
+            Variant::I8085 => Ok(()),
+            Variant::I8080 => Err(EmulatorErrors::InvalidArgument("the 8080 has no such pin")),
+        }
+    }
+
+    /// Drive an interrupt input, the interrupt is acknowledged after the
+    /// current instruction if it is enabled and not masked
+    pub fn set_interrupt_pin(&mut self, pin: InterruptPin, level: bool) -> Result<()> {
+        self.check_8085()?;
+        let pins = &mut self.pins;
+        let rising = level && !pins.levels[pin as usize];
+        pins.levels[pin as usize] = level;
+        match pin {
+            InterruptPin::Trap => pins.trap_latched |= rising,
+            InterruptPin::Rst75 => pins.rst75_latched |= rising,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Drive the serial input read by RIM
+    pub fn set_sid(&mut self, level: bool) -> Result<()> {
+        self.check_8085()?;
+        self.pins.sid = level;
+        Ok(())
+    }
+
+    /// The serial output written by SIM
+    pub fn sod(&self) -> bool {
+        self.pins.sod
+    }
+
+    /// The highest priority vectored interrupt to take now, if any
+    fn pending_pin(&self) -> Option<InterruptPin> {
+        let pins = &self.pins;
+        if pins.trap_latched && pins.levels[InterruptPin::Trap as usize] {
+            return Some(InterruptPin::Trap);
+        }
+        if !self.interrupt_enabled || self.interrupt_delayed {
+            return None;
+        }
+        let unmasked = |bit: u8| pins.masks & 1 << bit == 0;
+        if pins.rst75_latched && unmasked(2) {
+            Some(InterruptPin::Rst75)
+        } else if pins.levels[InterruptPin::Rst65 as usize] && unmasked(1) {
+            Some(InterruptPin::Rst65)
+        } else if pins.levels[InterruptPin::Rst55 as usize] && unmasked(0) {
+            Some(InterruptPin::Rst55)
+        } else {
+            None
+        }
+    }
+
+    /// TRAP, RST 7.5, 6.5 and 5.5 come before INTR,
+    /// returns the clock cycles used
+    pub(super) fn acknowledge_pin(&mut self) -> Result<u64> {
+        if self.variant != Variant::I8085 {
+            return Ok(0);
+        }
+        let Some(pin) = self.pending_pin() else {
+            return Ok(0);
+        };
+        match pin {
+            InterruptPin::Trap => {
+                self.pins.trap_latched = false;
+                self.pins.inte_before_trap = Some(self.interrupt_enabled)
+            }
+            InterruptPin::Rst75 => self.pins.rst75_latched = false,
+            _ => (),
+        }
+        self.halted = false;
+        self.interrupt_enabled = false;
+        self.push_pc()?;
+        self.pc = pin.vector();
+        // like a RST
+        let cycles = crate::CLOCK_CYCLES_8085[0xff] as u64;
+        self.cycles += cycles;
+        Ok(cycles)
+    }
+
+    /// SID, the pending RST 7.5, 6.5 and 5.5, INTE and the masks
+    fn rim(&mut self) {
+        let inte = match self.pins.inte_before_trap.take() {
+            Some(inte) => inte,
+            None => self.interrupt_enabled,
+        };
+        let pins = &self.pins;
+        self.reg_a = (pins.sid as u8) << 7
+            | (pins.rst75_latched as u8) << 6
+            | (pins.levels[InterruptPin::Rst65 as usize] as u8) << 5
+            | (pins.levels[InterruptPin::Rst55 as usize] as u8) << 4
+            | (inte as u8) << 3
+            | pins.masks;
+    }
+
+    /// Bit 3 enables setting the masks from bits 0-2, bit 4 resets
+    /// the RST 7.5 latch, bit 6 enables writing bit 7 to SOD
+    fn sim(&mut self) {
+        let a = self.reg_a;
+        if a & 0x08 != 0 {
+            self.pins.masks = a & 0x07
+        }
+        if a & 0x10 != 0 {
+            self.pins.rst75_latched = false
+        }
+        if a & 0x40 != 0 {
+            self.pins.sod = a & 0x80 != 0
+        }
+    }
+
+    /// HL - BC, with the flags of a 16-bit subtraction
+    fn dsub(&mut self) {
+        let lo = self.set_condition_bits(self.reg_l, !self.reg_c, true);
+        let hi = self.set_condition_bits(self.reg_h, !self.reg_b, lo > u8::MAX.into());
+        self.conditon_codes.set_carry(hi <= u8::MAX.into());
+        self.conditon_codes.set_zero(lo as u8 == 0 && hi as u8 == 0);
+        (self.reg_l, self.reg_h) = (lo as u8, hi as u8);
+    }
+
+    /// HL shifted right, bit 15 kept, bit 0 into the carry
+    fn arhl(&mut self) {
+        let hl = u16::from_le_bytes([self.reg_l, self.reg_h]);
+        self.conditon_codes.set_carry(hl & 1 == 1);
+        [self.reg_l, self.reg_h] = ((hl as i16 >> 1) as u16).to_le_bytes();
+    }
+
+    /// DE rotated left through the carry
+    fn rdel(&mut self) {
+        let de = u16::from_le_bytes([self.reg_e, self.reg_d]);
+        let carry = self.conditon_codes.is_carry_set() as u16;
+        self.conditon_codes.set_carry(de & 0x8000 != 0);
+        [self.reg_e, self.reg_d] = (de << 1 | carry).to_le_bytes();
+    }
+
+    /// DE = `base` + the immediate byte
+    fn load_de_indexed(&mut self, base: u16) -> Result<()> {
+        let offset = self.load_d8_operand()?;
+        [self.reg_e, self.reg_d] = base.wrapping_add(offset as u16).to_le_bytes();
+        Ok(())
+    }
+
+    fn jump_on_k(&mut self, condition: bool) -> Result<bool> {
+        if condition {
+            self.jmp()?;
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
+        Ok(condition)
+    }
+
+    /// The opcodes which differ from the 8080, `None` for the others.
+    /// Returns whether a conditional branch is taken
+    pub(super) fn execute_8085(&mut self, opcode: u8) -> Result<Option<bool>> {
+        let de = u16::from_le_bytes([self.reg_e, self.reg_d]);
+        let mut taken = true;
+        match opcode {
+            0x08 => self.dsub(),
+            0x10 => self.arhl(),
+            0x18 => self.rdel(),
+            0x20 => self.rim(),
+            0x28 => self.load_de_indexed(u16::from_le_bytes([self.reg_l, self.reg_h]))?,
+            0x30 => self.sim(),
+            0x38 => self.load_de_indexed(self.sp)?,
+            // RSTV
+            0xcb => {
+                taken = self.conditon_codes.is_overflow_set();
+                if taken {
+                    self.push_pc()?;
+                    self.pc = 0x40
+                }
+            }
+            // SHLX
+            0xd9 => {
+                self.store_to_memory(de, self.reg_l)?;
+                self.store_to_memory(de.wrapping_add(1), self.reg_h)?
+            }
+            0xdd => taken = self.jump_on_k(!self.conditon_codes.is_k_set())?,
+            // LHLX
+            0xed => {
+                self.reg_l = self.load_byte_from_memory(de)?;
+                self.reg_h = self.load_byte_from_memory(de.wrapping_add(1))?
+            }
+            0xfd => taken = self.jump_on_k(self.conditon_codes.is_k_set())?,
+            _ => return Ok(None),
+        }
+        Ok(Some(taken))
+    }
+
+    pub(super) fn psw(&self) -> u8 {
+        match self.variant {
+            Variant::I8080 => self.conditon_codes.to_psw(),
+            Variant::I8085 => self.conditon_codes.to_psw_8085(),
+        }
+    }
+
+    pub(super) fn set_psw(&mut self, psw: u8) {
+        self.conditon_codes = match self.variant {
+            Variant::I8080 => ConditionCodes::from_psw(psw),
+            Variant::I8085 => ConditionCodes::from_psw_8085(psw),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assemble, Cpu8080, EmulatorErrors, InterruptPin, MemoryBus, Variant};
+
+    fn cpu_8085(source: &str) -> Cpu8080<crate::RomRam, ()> {
+        let (mut cpu, _) = Cpu8080::new(assemble(source).unwrap(), vec![0; 0x100], ());
+        cpu.set_variant(Variant::I8085);
+        cpu
+    }
+
+    #[test]
+    fn interrupt_pins() {
+        let mut cpu = cpu_8085(
+            "
+        LXI     SP,0100H
+        MVI     A,0CEH      ; SOD high, mask RST 7.5 and 6.5
+        DB      30H         ; SIM
+        EI
+        NOP
+        NOP
+        JMP     0008H
+        ORG     0024H
+        HLT
+        ORG     002CH
+        DB      20H         ; RIM
+        HLT
+",
+        );
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert!(cpu.sod());
+        assert_eq!(cpu.pins.masks, 0x06);
+
+        // masked, 7.5 is still latched
+        cpu.set_interrupt_pin(InterruptPin::Rst75, true).unwrap();
+        cpu.set_interrupt_pin(InterruptPin::Rst65, true).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x0008);
+
+        cpu.set_interrupt_pin(InterruptPin::Rst55, true).unwrap();
+        let info = cpu.step().unwrap();
+        assert_eq!((info.pc, info.cycles), (0x002c, 4 + 12));
+        assert!(!cpu.interrupt_enabled);
+        assert_eq!(
+            (
+                cpu.memory.read(0xfe).unwrap(),
+                cpu.memory.read(0xff).unwrap()
+            ),
+            (0x09, 0x00)
+        );
+        cpu.step().unwrap();
+        assert_eq!(cpu.reg_a, 0x76);
+
+        // TRAP wakes the CPU up with interrupts disabled, once per rising edge
+        cpu.step().unwrap();
+        assert!(cpu.is_halted());
+        cpu.set_interrupt_pin(InterruptPin::Trap, true).unwrap();
+        cpu.step().unwrap();
+        assert_eq!((cpu.pc, cpu.is_halted()), (0x0024, false));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!((cpu.pc, cpu.is_halted()), (0x0025, true));
+        cpu.set_interrupt_pin(InterruptPin::Trap, false).unwrap();
+        cpu.set_interrupt_pin(InterruptPin::Trap, true).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x0024);
+
+        let (mut cpu, _) = Cpu8080::new(vec![0], vec![], ());
+        assert!(matches!(
+            cpu.set_interrupt_pin(InterruptPin::Trap, true),
+            Err(EmulatorErrors::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn undocumented_instructions() {
+        let mut cpu = cpu_8085(
+            "
+        LXI     SP,00F0H
+        LXI     B,1234H
+        LXI     H,1000H
+        DB      08H         ; DSUB
+        DB      10H         ; ARHL
+        LXI     D,8001H
+        STC
+        DB      18H         ; RDEL
+        DB      28H,10H     ; LDHI 10H
+        DB      38H,02H     ; LDSI 02H
+        DB      0D9H        ; SHLX
+        LXI     H,0
+        DB      0EDH        ; LHLX
+        HLT
+",
+        );
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((cpu.reg_h, cpu.reg_l), (0xfd, 0xcc));
+        assert!(cpu.conditon_codes.is_carry_set() && cpu.conditon_codes.is_k_set());
+        cpu.step().unwrap();
+        assert_eq!((cpu.reg_h, cpu.reg_l), (0xfe, 0xe6));
+        assert!(!cpu.conditon_codes.is_carry_set());
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((cpu.reg_d, cpu.reg_e), (0x00, 0x03));
+        assert!(cpu.conditon_codes.is_carry_set());
+        cpu.step().unwrap();
+        assert_eq!((cpu.reg_d, cpu.reg_e), (0xfe, 0xf6));
+        cpu.step().unwrap();
+        assert_eq!((cpu.reg_d, cpu.reg_e), (0x00, 0xf2));
+        cpu.step().unwrap();
+        assert_eq!(
+            (
+                cpu.memory.read(0xf2).unwrap(),
+                cpu.memory.read(0xf3).unwrap()
+            ),
+            (0xe6, 0xfe)
+        );
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!((cpu.reg_h, cpu.reg_l), (0xfe, 0xe6));
+    }
+
+    #[test]
+    fn overflow_and_k_flags() {
+        let mut cpu = cpu_8085(
+            "
+        LXI     SP,00F0H
+        MVI     A,7FH
+        ADI     1
+        DB      0DDH        ; JNK
+        DW      OVERFLOW
+        HLT
+OVERFLOW:
+        DB      0CBH        ; RSTV
+        ORG     40H
+        PUSH    PSW
+        POP     B
+        LXI     H,0FFFFH
+        INX     H
+        DB      0FDH        ; JK
+        DW      DONE
+        HLT
+DONE:   HLT
+",
+        );
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.step().unwrap().cycles, 7);
+        assert!(cpu.conditon_codes.is_overflow_set() && !cpu.conditon_codes.is_k_set());
+        assert_eq!(cpu.step().unwrap().cycles, 10);
+        assert_eq!(cpu.step().unwrap().cycles, 12);
+        assert_eq!(cpu.pc, 0x0040);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        // S Z K AC 0 P V C
+        assert_eq!(cpu.reg_c, 0x92);
+        assert_eq!(cpu.state().flags, 0x92);
+        assert!(cpu.state().condition_codes().is_overflow_set());
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.conditon_codes.is_k_set());
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0x004b);
+    }
+
+    #[test]
+    fn decoded_by_variant() {
+        let mut cpu = cpu_8085(
+            "
+        LXI     SP,00F0H
+        MVI     A,7FH
+        ADI     1
+        DB      0CBH        ; RSTV
+",
+        );
+        let buffer = crate::TraceBuffer::new(1);
+        cpu.set_tracer(buffer.clone());
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(
+            buffer.records()[0].format(crate::TraceFormat::Listing),
+            "0007: CB        RSTV           A=80 BC=0000 DE=0000 HL=0000 SP=00F0 F=S-A---V CYC=24"
+        );
+        assert_eq!(cpu.pc, 0x0040);
+
+        // LHLX is a whole instruction on the 8085, a truncated CALL on the 8080
+        assert_eq!(cpu.interrupt(&[0xed], false).unwrap(), 0);
+        let (mut i8080, _) = Cpu8080::new(vec![0], vec![], ());
+        assert!(matches!(
+            i8080.interrupt(&[0xed], false),
+            Err(EmulatorErrors::InvalidArgument(_))
+        ));
+        assert!(crate::is_call(0xcb, Variant::I8085) && !crate::is_call(0xdd, Variant::I8085));
+    }
+}
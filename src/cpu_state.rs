@@ -0,0 +1,62 @@
+use crate::{ConditionCodes, Variant};
+
+/// A snapshot of all the registers and flags of the CPU,
+/// see `Cpu8080::state` and `Cpu8080::set_state`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    pub reg_a: u8,
+    pub reg_b: u8,
+    pub reg_c: u8,
+    pub reg_d: u8,
+    pub reg_e: u8,
+    pub reg_h: u8,
+    pub reg_l: u8,
+    /// Condition codes as pushed by PUSH PSW, see `ConditionCodes::to_psw`
+    /// and `ConditionCodes::to_psw_8085`
+    pub flags: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub interrupt_enabled: bool,
+    /// HLT was executed, waiting for an interrupt
+    pub halted: bool,
+    /// The CPU the state was taken from, which lays out `flags`,
+    /// `Cpu8080::set_state` keeps the variant of the CPU
+    pub variant: Variant,
+}
+
+macro_rules! generate_reg_pair_accessors {
+    ( $( ($get:ident, $set:ident, $reg_hi:ident, $reg_lo:ident) ),* ) => {
+        $(
+            pub fn $get(&self) -> u16 {
+                u16::from_le_bytes([self.$reg_lo, self.$reg_hi])
+            }
+
+            pub fn $set(&mut self, value: u16) {
+                [self.$reg_lo, self.$reg_hi] = value.to_le_bytes();
+            }
+        )*
+    };
+}
+
+impl CpuState {
+    generate_reg_pair_accessors![
+        (bc, set_bc, reg_b, reg_c),
+        (de, set_de, reg_d, reg_e),
+        (hl, set_hl, reg_h, reg_l)
+    ];
+
+    pub fn condition_codes(&self) -> ConditionCodes {
+        match self.variant {
+            Variant::I8080 => ConditionCodes::from_psw(self.flags),
+            Variant::I8085 => ConditionCodes::from_psw_8085(self.flags),
+        }
+    }
+
+    pub fn set_condition_codes(&mut self, condition_codes: ConditionCodes) {
+        self.flags = match self.variant {
+            Variant::I8080 => condition_codes.to_psw(),
+            Variant::I8085 => condition_codes.to_psw_8085(),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::fmt::Display;
+
+use crate::{instruction_length, MemoryBus, Result, Variant};
+
+/// The mnemonics to render instructions with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// `MOV A,M`, as in the Intel manuals
+    Intel,
+    /// `LD A,(HL)`, as the Z80 spells the same instruction
+    Zilog,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(&'static str),
+    Byte(u8),
+    Word(u16),
+    /// Where a jump or a call goes
+    Target(u16),
+    /// The address of the data loaded or stored
+    Memory(u16),
+    Port(u8),
+    /// The number of a RST
+    Vector(u8),
+}
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub len: u16,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub syntax: Syntax,
+    /// An opcode missing from the manuals, which the
+    /// CPU executes like the instruction it renders as
+    pub undocumented: bool,
+}
+
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "M", "A"];
+const PAIRS: [&str; 4] = ["B", "D", "H", "SP"];
+const STACK_PAIRS: [&str; 4] = ["B", "D", "H", "PSW"];
+const RET_CC: [&str; 8] = ["RNZ", "RZ", "RNC", "RC", "RPO", "RPE", "RP", "RM"];
+const JMP_CC: [&str; 8] = ["JNZ", "JZ", "JNC", "JC", "JPO", "JPE", "JP", "JM"];
+const CALL_CC: [&str; 8] = ["CNZ", "CZ", "CNC", "CC", "CPO", "CPE", "CP", "CM"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+const ALU_IMMEDIATE: [&str; 8] = ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+const ROTATES: [&str; 8] = ["RLC", "RRC", "RAL", "RAR", "DAA", "CMA", "STC", "CMC"];
+
+const Z80_REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const Z80_PAIRS: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const Z80_STACK_PAIRS: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const Z80_CONDITIONS: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const Z80_ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+const Z80_ROTATES: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+type Decoded = (&'static str, Vec<Operand>, bool);
+
+/// The opcode is split as xxyyyzzz, yyy is also ppq
+fn intel(opcode: u8, byte: u8, word: u16) -> Decoded {
+    use Operand::*;
+    let (x, y, z) = (
+        opcode >> 6,
+        (opcode >> 3 & 7) as usize,
+        (opcode & 7) as usize,
+    );
+    let (p, q) = (y >> 1, y & 1);
+    let documented = |mnemonic, operands| (mnemonic, operands, false);
+    match (x, z) {
+        (0, 0) => ("NOP", vec![], opcode != 0),
+        (0, 1) if q == 0 => documented("LXI", vec![Register(PAIRS[p]), Word(word)]),
+        (0, 1) => documented("DAD", vec![Register(PAIRS[p])]),
+        (0, 2) => match (p, q) {
+            (0 | 1, 0) => documented("STAX", vec![Register(PAIRS[p])]),
+            (2, 0) => documented("SHLD", vec![Memory(word)]),
+            (3, 0) => documented("STA", vec![Memory(word)]),
+            (0 | 1, _) => documented("LDAX", vec![Register(PAIRS[p])]),
+            (2, _) => documented("LHLD", vec![Memory(word)]),
+            _ => documented("LDA", vec![Memory(word)]),
+        },
+        (0, 3) if q == 0 => documented("INX", vec![Register(PAIRS[p])]),
+        (0, 3) => documented("DCX", vec![Register(PAIRS[p])]),
+        (0, 4) => documented("INR", vec![Register(REGISTERS[y])]),
+        (0, 5) => documented("DCR", vec![Register(REGISTERS[y])]),
+        (0, 6) => documented("MVI", vec![Register(REGISTERS[y]), Byte(byte)]),
+        (0, _) => documented(ROTATES[y], vec![]),
+        (1, _) if opcode == 0x76 => documented("HLT", vec![]),
+        (1, _) => documented("MOV", vec![Register(REGISTERS[y]), Register(REGISTERS[z])]),
+        (2, _) => documented(ALU[y], vec![Register(REGISTERS[z])]),
+        (_, 0) => documented(RET_CC[y], vec![]),
+        (_, 1) if q == 0 => documented("POP", vec![Register(STACK_PAIRS[p])]),
+        (_, 1) => (["RET", "RET", "PCHL", "SPHL"][p], vec![], p == 1),
+        (_, 2) => documented(JMP_CC[y], vec![Target(word)]),
+        (_, 3) => match y {
+            0 | 1 => ("JMP", vec![Target(word)], y == 1),
+            2 => documented("OUT", vec![Port(byte)]),
+            3 => documented("IN", vec![Port(byte)]),
+            _ => documented(["XTHL", "XCHG", "DI", "EI"][y - 4], vec![]),
+        },
+        (_, 4) => documented(CALL_CC[y], vec![Target(word)]),
+        (_, 5) if q == 0 => documented("PUSH", vec![Register(STACK_PAIRS[p])]),
+        (_, 5) => ("CALL", vec![Target(word)], p != 0),
+        (_, 6) => documented(ALU_IMMEDIATE[y], vec![Byte(byte)]),
+        _ => documented("RST", vec![Vector(y as u8)]),
+    }
+}
+
+fn zilog(opcode: u8, byte: u8, word: u16) -> Decoded {
+    use Operand::*;
+    let (x, y, z) = (
+        opcode >> 6,
+        (opcode >> 3 & 7) as usize,
+        (opcode & 7) as usize,
+    );
+    let (p, q) = (y >> 1, y & 1);
+    let documented = |mnemonic, operands| (mnemonic, operands, false);
+    let alu = |operand| match y {
+        0 | 1 | 3 => documented(Z80_ALU[y], vec![Register("A"), operand]),
+        _ => documented(Z80_ALU[y], vec![operand]),
+    };
+    match (x, z) {
+        (0, 0) => ("NOP", vec![], opcode != 0),
+        (0, 1) if q == 0 => documented("LD", vec![Register(Z80_PAIRS[p]), Word(word)]),
+        (0, 1) => documented("ADD", vec![Register("HL"), Register(Z80_PAIRS[p])]),
+        (0, 2) => match (p, q) {
+            (0, 0) => documented("LD", vec![Register("(BC)"), Register("A")]),
+            (1, 0) => documented("LD", vec![Register("(DE)"), Register("A")]),
+            (2, 0) => documented("LD", vec![Memory(word), Register("HL")]),
+            (3, 0) => documented("LD", vec![Memory(word), Register("A")]),
+            (0, _) => documented("LD", vec![Register("A"), Register("(BC)")]),
+            (1, _) => documented("LD", vec![Register("A"), Register("(DE)")]),
+            (2, _) => documented("LD", vec![Register("HL"), Memory(word)]),
+            _ => documented("LD", vec![Register("A"), Memory(word)]),
+        },
+        (0, 3) if q == 0 => documented("INC", vec![Register(Z80_PAIRS[p])]),
+        (0, 3) => documented("DEC", vec![Register(Z80_PAIRS[p])]),
+        (0, 4) => documented("INC", vec![Register(Z80_REGISTERS[y])]),
+        (0, 5) => documented("DEC", vec![Register(Z80_REGISTERS[y])]),
+        (0, 6) => documented("LD", vec![Register(Z80_REGISTERS[y]), Byte(byte)]),
+        (0, _) => documented(Z80_ROTATES[y], vec![]),
+        (1, _) if opcode == 0x76 => documented("HALT", vec![]),
+        (1, _) => documented(
+            "LD",
+            vec![Register(Z80_REGISTERS[y]), Register(Z80_REGISTERS[z])],
+        ),
+        (2, _) => alu(Register(Z80_REGISTERS[z])),
+        (_, 0) => documented("RET", vec![Register(Z80_CONDITIONS[y])]),
+        (_, 1) if q == 0 => documented("POP", vec![Register(Z80_STACK_PAIRS[p])]),
+        (_, 1) => match p {
+            0 | 1 => ("RET", vec![], p == 1),
+            2 => documented("JP", vec![Register("(HL)")]),
+            _ => documented("LD", vec![Register("SP"), Register("HL")]),
+        },
+        (_, 2) => documented("JP", vec![Register(Z80_CONDITIONS[y]), Target(word)]),
+        (_, 3) => match y {
+            0 | 1 => ("JP", vec![Target(word)], y == 1),
+            2 => documented("OUT", vec![Port(byte), Register("A")]),
+            3 => documented("IN", vec![Register("A"), Port(byte)]),
+            4 => documented("EX", vec![Register("(SP)"), Register("HL")]),
+            5 => documented("EX", vec![Register("DE"), Register("HL")]),
+            _ => documented(["DI", "EI"][y - 6], vec![]),
+        },
+        (_, 4) => documented("CALL", vec![Register(Z80_CONDITIONS[y]), Target(word)]),
+        (_, 5) if q == 0 => documented("PUSH", vec![Register(Z80_STACK_PAIRS[p])]),
+        (_, 5) => ("CALL", vec![Target(word)], p != 0),
+        (_, 6) => alu(Byte(byte)),
+        _ => documented("RST", vec![Vector(y as u8)]),
+    }
+}
+
+/// The opcodes the 8085 decodes differently, in both syntaxes as
+/// the Z80 has none of them. Only RIM and SIM are documented
+fn i8085(opcode: u8, byte: u8, word: u16) -> Option<Decoded> {
+    use Operand::*;
+    let (mnemonic, operands) = match opcode {
+        0x08 => ("DSUB", vec![]),
+        0x10 => ("ARHL", vec![]),
+        0x18 => ("RDEL", vec![]),
+        0x20 => return Some(("RIM", vec![], false)),
+        0x28 => ("LDHI", vec![Byte(byte)]),
+        0x30 => return Some(("SIM", vec![], false)),
+        0x38 => ("LDSI", vec![Byte(byte)]),
+        0xcb => ("RSTV", vec![]),
+        0xd9 => ("SHLX", vec![]),
+        0xdd => ("JNK", vec![Target(word)]),
+        0xed => ("LHLX", vec![]),
+        0xfd => ("JK", vec![Target(word)]),
+        _ => return None,
+    };
+    Some((mnemonic, operands, true))
+}
+
+/// Hexadecimal the way assemblers read it: `0FFH`
+fn hex(value: u16, digits: usize) -> String {
+    let text = format!("{value:0digits$X}H");
+    if text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("0{text}")
+    } else {
+        text
+    }
+}
+
+impl Operand {
+    fn render(&self, syntax: Syntax, label: &impl Fn(u16) -> Option<String>) -> String {
+        match (self, syntax) {
+            (Operand::Register(name), _) => name.to_string(),
+            (Operand::Byte(value), _) => hex(*value as u16, 2),
+            (Operand::Word(value), _) => hex(*value, 4),
+            (Operand::Target(addr), _) => label(*addr).unwrap_or_else(|| hex(*addr, 4)),
+            (Operand::Memory(addr), Syntax::Intel) => hex(*addr, 4),
+            (Operand::Memory(addr), Syntax::Zilog) => format!("({})", hex(*addr, 4)),
+            (Operand::Port(port), Syntax::Intel) => hex(*port as u16, 2),
+            (Operand::Port(port), Syntax::Zilog) => format!("({})", hex(*port as u16, 2)),
+            (Operand::Vector(n), Syntax::Intel) => n.to_string(),
+            (Operand::Vector(n), Syntax::Zilog) => hex(*n as u16 * 8, 2),
+        }
+    }
+}
+
+impl Instruction {
+    /// The address a jump, a call or a RST goes to
+    pub fn target(&self) -> Option<u16> {
+        self.operands.iter().find_map(|operand| match operand {
+            Operand::Target(addr) => Some(*addr),
+            Operand::Vector(n) => Some(*n as u16 * 8),
+            _ => None,
+        })
+    }
+
+    /// Render the instruction, jump and call targets
+    /// are replaced by `label` when it gives one
+    pub fn render(&self, label: impl Fn(u16) -> Option<String>) -> String {
+        let operands = self
+            .operands
+            .iter()
+            .map(|operand| operand.render(self.syntax, &label))
+            .collect::<Vec<_>>();
+        if operands.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operands.join(","))
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(|_| None))
+    }
+}
+
+/// Decode the instruction `variant` executes at the start
+/// of `bytes`, None if they are too short to hold all of it
+pub fn disassemble(bytes: &[u8], syntax: Syntax, variant: Variant) -> Option<Instruction> {
+    let &opcode = bytes.first()?;
+    let len = instruction_length(opcode, variant);
+    let operands = bytes.get(1..len as usize)?;
+    let byte = operands.first().copied().unwrap_or_default();
+    let word = match operands {
+        [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+        _ => 0,
+    };
+    let decoded = match variant {
+        Variant::I8080 => None,
+        Variant::I8085 => i8085(opcode, byte, word),
+    };
+    let (mnemonic, operands, undocumented) = decoded.unwrap_or_else(|| match syntax {
+        Syntax::Intel => intel(opcode, byte, word),
+        Syntax::Zilog => zilog(opcode, byte, word),
+    });
+    Some(Instruction {
+        opcode,
+        len,
+        mnemonic,
+        operands,
+        syntax,
+        undocumented,
+    })
+}
+
+/// Decode the instruction at `addr` as the CPU fetches it
+pub fn disassemble_at(
+    memory: &impl MemoryBus,
+    addr: u16,
+    syntax: Syntax,
+    variant: Variant,
+) -> Result<Instruction> {
+    let opcode = memory.read(addr)?;
+    let bytes = (0..instruction_length(opcode, variant))
+        .map(|i| memory.read(addr.wrapping_add(i)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(disassemble(&bytes, syntax, variant).expect("all the bytes are read"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_syntaxes() {
+        let cases: &[(&[u8], &str, &str)] = &[
+            (&[0x3e, 0x05], "MVI A,05H", "LD A,05H"),
+            (&[0x7e], "MOV A,M", "LD A,(HL)"),
+            (&[0x32, 0x00, 0x24], "STA 2400H", "LD (2400H),A"),
+            (&[0xc2, 0xaf, 0x01], "JNZ 01AFH", "JP NZ,01AFH"),
+            (&[0xdb, 0xff], "IN 0FFH", "IN A,(0FFH)"),
+            (&[0xf5], "PUSH PSW", "PUSH AF"),
+            (&[0x9e], "SBB M", "SBC A,(HL)"),
+            (&[0xfe, 0x10], "CPI 10H", "CP 10H"),
+            (&[0xcf], "RST 1", "RST 08H"),
+            (&[0xe9], "PCHL", "JP (HL)"),
+            (&[0x27], "DAA", "DAA"),
+        ];
+        for (bytes, intel, zilog) in cases {
+            assert_eq!(
+                disassemble(bytes, Syntax::Intel, Variant::I8080)
+                    .unwrap()
+                    .to_string(),
+                *intel
+            );
+            assert_eq!(
+                disassemble(bytes, Syntax::Zilog, Variant::I8080)
+                    .unwrap()
+                    .to_string(),
+                *zilog
+            );
+        }
+        assert!(disassemble(&[0xc3, 0x00], Syntax::Intel, Variant::I8080).is_none());
+    }
+
+    #[test]
+    fn all_opcodes() {
+        let mut undocumented = vec![];
+        for opcode in 0..=0xff {
+            let instruction =
+                disassemble(&[opcode, 0x34, 0x12], Syntax::Intel, Variant::I8080).unwrap();
+            assert_eq!(instruction.len, instruction_length(opcode, Variant::I8080));
+            if instruction.undocumented {
+                undocumented.push((opcode, instruction.to_string()));
+            }
+        }
+        let aliases = [
+            (0xcb, "JMP 1234H"),
+            (0xd9, "RET"),
+            (0xdd, "CALL 1234H"),
+            (0xed, "CALL 1234H"),
+            (0xfd, "CALL 1234H"),
+        ];
+        let nops = (1..8).map(|y| (y << 3, "NOP"));
+        assert_eq!(
+            undocumented,
+            nops.chain(aliases)
+                .map(|(opcode, text)| (opcode, text.to_string()))
+                .collect::<Vec<_>>()
+        );
+        let call = disassemble(&[0xcd, 0x34, 0x12], Syntax::Intel, Variant::I8080).unwrap();
+        assert_eq!(call.target(), Some(0x1234));
+        assert_eq!(
+            call.render(|addr| Some(format!("L{addr:04X}"))),
+            "CALL L1234"
+        );
+    }
+
+    #[test]
+    fn i8085_opcodes() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x20], "RIM"),
+            (&[0x28, 0x10], "LDHI 10H"),
+            (&[0xcb], "RSTV"),
+            (&[0xdd, 0x34, 0x12], "JNK 1234H"),
+            (&[0xed], "LHLX"),
+            (&[0x3e, 0x05], "MVI A,05H"),
+        ];
+        for (bytes, text) in cases {
+            let instruction = disassemble(bytes, Syntax::Intel, Variant::I8085).unwrap();
+            assert_eq!(instruction.to_string(), *text);
+            assert_eq!(instruction.len as usize, bytes.len());
+        }
+        for opcode in 0..=0xff {
+            let instruction =
+                disassemble(&[opcode, 0x34, 0x12], Syntax::Zilog, Variant::I8085).unwrap();
+            assert_eq!(instruction.len, instruction_length(opcode, Variant::I8085));
+        }
+    }
+}
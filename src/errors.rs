@@ -1,12 +1,65 @@
-use std::io;
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{c_char, CString},
+    fmt::Display,
+    io,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
 
 #[derive(Debug)]
 pub struct MemoryOutOfBounds;
 
+#[derive(Debug)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The state was saved with another ROM loaded
+    RomMismatch,
+    /// The state was saved from the other `Variant`
+    VariantMismatch,
+    RamSizeMismatch,
+    Truncated,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    SizeMismatch {
+        load_address: u16,
+        expected: usize,
+        actual: usize,
+    },
+    ChecksumMismatch {
+        load_address: u16,
+        expected: u32,
+        actual: u32,
+    },
+    /// Two chips are loaded at overlapping addresses
+    Overlap { load_address: u16 },
+    /// The chip does not fit in the 64 KiB address space
+    TooLarge { load_address: u16 },
+}
+
+/// The assembler rejected the source at `line`, counted from 1
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
 #[derive(Debug)]
 pub enum EmulatorErrors {
     Io(io::Error),
     MemoryOutOfBounds(MemoryOutOfBounds),
+    SaveState(SaveStateError),
+    Rom(RomError),
+    Asm(AsmError),
+    UnsupportedInterrupt(u8),
+    /// An argument handed over by the app is unusable, e.g. a null pointer
+    InvalidArgument(&'static str),
+    /// The other end of the message channel is gone
+    Disconnected,
 }
 
 impl From<io::Error> for EmulatorErrors {
@@ -20,3 +73,195 @@ impl From<MemoryOutOfBounds> for EmulatorErrors {
         Self::MemoryOutOfBounds(value)
     }
 }
+
+impl From<SaveStateError> for EmulatorErrors {
+    fn from(value: SaveStateError) -> Self {
+        Self::SaveState(value)
+    }
+}
+
+impl From<RomError> for EmulatorErrors {
+    fn from(value: RomError) -> Self {
+        Self::Rom(value)
+    }
+}
+
+impl From<AsmError> for EmulatorErrors {
+    fn from(value: AsmError) -> Self {
+        Self::Asm(value)
+    }
+}
+
+impl Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a save state"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {version}")
+            }
+            Self::RomMismatch => write!(f, "save state was made with another ROM"),
+            Self::VariantMismatch => write!(f, "save state was made with another CPU variant"),
+            Self::RamSizeMismatch => write!(f, "save state RAM size does not match"),
+            Self::Truncated => write!(f, "save state is truncated"),
+        }
+    }
+}
+
+impl Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SizeMismatch {
+                load_address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "ROM at {load_address:#06x} is {actual} bytes, expected {expected}"
+            ),
+            Self::ChecksumMismatch {
+                load_address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "ROM at {load_address:#06x} has CRC32 {actual:08x}, expected {expected:08x}"
+            ),
+            Self::Overlap { load_address } => {
+                write!(f, "ROM at {load_address:#06x} overlaps another one")
+            }
+            Self::TooLarge { load_address } => {
+                write!(f, "ROM at {load_address:#06x} exceeds the address space")
+            }
+        }
+    }
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Display for EmulatorErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "IO error: {error}"),
+            Self::MemoryOutOfBounds(_) => write!(f, "memory access out of bounds"),
+            Self::SaveState(error) => error.fmt(f),
+            Self::Rom(error) => error.fmt(f),
+            Self::Asm(error) => error.fmt(f),
+            Self::UnsupportedInterrupt(irq_no) => write!(f, "unsupported IRQ {irq_no}"),
+            Self::InvalidArgument(reason) => write!(f, "invalid argument: {reason}"),
+            Self::Disconnected => write!(f, "message channel disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorErrors {}
+
+/// The outcome of the `extern "C"` functions, details
+/// of the last failure are given by `last_error_message`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorStatus {
+    Ok,
+    Io,
+    MemoryOutOfBounds,
+    InvalidSaveState,
+    InvalidRom,
+    UnsupportedInterrupt,
+    InvalidArgument,
+    Disconnected,
+    /// A bug in the emulator, please open an issue
+    Panic,
+}
+
+impl From<&EmulatorErrors> for EmulatorStatus {
+    fn from(value: &EmulatorErrors) -> Self {
+        match value {
+            EmulatorErrors::Io(_) => Self::Io,
+            EmulatorErrors::MemoryOutOfBounds(_) => Self::MemoryOutOfBounds,
+            EmulatorErrors::SaveState(_) => Self::InvalidSaveState,
+            EmulatorErrors::Rom(_) => Self::InvalidRom,
+            EmulatorErrors::UnsupportedInterrupt(_) => Self::UnsupportedInterrupt,
+            EmulatorErrors::Asm(_) | EmulatorErrors::InvalidArgument(_) => Self::InvalidArgument,
+            EmulatorErrors::Disconnected => Self::Disconnected,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Run `f` on the C side of the boundary: errors and panics never
+/// cross it, they are recorded for `last_error_message` instead
+pub(crate) fn catch_errors<T>(f: impl FnOnce() -> crate::Result<T>) -> Result<T, EmulatorStatus> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(error)) => {
+            set_last_error(error.to_string());
+            Err((&error).into())
+        }
+        Err(payload) => {
+            set_last_error(format!("panic: {}", panic_message(payload.as_ref())));
+            Err(EmulatorStatus::Panic)
+        }
+    }
+}
+
+pub(crate) fn status_of(f: impl FnOnce() -> crate::Result<()>) -> EmulatorStatus {
+    match catch_errors(f) {
+        Ok(()) => EmulatorStatus::Ok,
+        Err(status) => status,
+    }
+}
+
+/// The message of the last error which happened on the calling thread,
+/// valid until the next error on that thread, null if none happened.
+#[no_mangle]
+pub extern "C" fn last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    #[test]
+    fn errors_do_not_cross_the_boundary() {
+        assert_eq!(status_of(|| Ok(())), EmulatorStatus::Ok);
+        assert_eq!(
+            status_of(|| Err(EmulatorErrors::UnsupportedInterrupt(9))),
+            EmulatorStatus::UnsupportedInterrupt
+        );
+        let message = unsafe { CStr::from_ptr(last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "unsupported IRQ 9");
+
+        assert_eq!(status_of(|| panic!("boom")), EmulatorStatus::Panic);
+        let message = unsafe { CStr::from_ptr(last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "panic: boom");
+    }
+}
@@ -0,0 +1,47 @@
+use std::ffi::c_void;
+
+use crate::IoCallbacks;
+
+/// Devices wired to the `IN` and `OUT` instructions
+pub trait PortIo {
+    /// IN port, the returned value is set back to reg_a
+    fn input(&mut self, port: u8) -> u8;
+
+    /// OUT port value, reg_a is passed as the value
+    fn output(&mut self, port: u8, value: u8);
+}
+
+/// No devices attached, reading any port gives 0
+impl PortIo for () {
+    fn input(&mut self, _port: u8) -> u8 {
+        0
+    }
+
+    fn output(&mut self, _port: u8, _value: u8) {}
+}
+
+/// Adapter servicing the IO ports through the C callbacks
+/// handed over by the app, see `new_cpu_instance`
+pub struct CallbackIo {
+    callbacks: IoCallbacks,
+    io_object: *const c_void,
+}
+
+impl CallbackIo {
+    pub fn new(callbacks: IoCallbacks, io_object: *const c_void) -> Self {
+        CallbackIo {
+            callbacks,
+            io_object,
+        }
+    }
+}
+
+impl PortIo for CallbackIo {
+    fn input(&mut self, port: u8) -> u8 {
+        (self.callbacks.input)(self.io_object, port)
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        (self.callbacks.output)(self.io_object, port, value)
+    }
+}
@@ -1,29 +1,78 @@
+mod asm;
+mod checksum;
+mod clock;
 mod clock_cycles;
 mod condition_codes;
+mod cpm;
 mod cpu;
+mod cpu_state;
+mod disasm;
 mod errors;
+mod io;
+pub mod machines;
+mod memory;
+mod rom;
+mod save_state;
+mod scheduler;
+mod trace;
+mod video;
 
-#[cfg(not(feature = "cpu_diag"))]
 use std::{
-    ffi::{c_char, c_void, CStr},
-    fs::File,
-    io::{BufReader, Read},
+    ffi::{c_char, c_void, CStr, CString},
     path::PathBuf,
-    str::FromStr,
+    ptr, slice,
     sync::mpsc::Sender,
+    time::Duration,
 };
 
-pub use errors::{EmulatorErrors, MemoryOutOfBounds};
+use errors::{catch_errors, status_of};
+use machines::space_invaders::{Button, SoundEvent, SpaceInvaders, FRAMEBUFFER_SIZE};
+
+pub use errors::{
+    last_error_message, AsmError, EmulatorErrors, EmulatorStatus, MemoryOutOfBounds, RomError,
+    SaveStateError,
+};
 
 pub type Result<T> = std::result::Result<T, EmulatorErrors>;
 
-pub use cpu::Cpu8080;
+pub use cpu::{
+    instruction_length, is_call, rst_instruction, Cpu8080, InterruptPin, StepInfo, Variant,
+};
+
+pub use cpu_state::CpuState;
+
+pub use asm::assemble;
+
+pub use disasm::{disassemble, disassemble_at, Instruction, Operand, Syntax};
+
+pub use save_state::SAVE_STATE_VERSION;
+
+pub use checksum::crc32;
+
+pub use cpm::{load_com, Bdos};
+
+pub use memory::{MemoryBus, RomRam};
+
+pub use scheduler::{EventAction, EventId};
+
+pub use trace::{TraceBuffer, TraceFormat, TraceRecord, TraceWriter, Tracer};
+
+pub use video::{
+    decode_frame, ColorBand, PixelFormat, Rotation, VideoLayout, SPACE_INVADERS_OVERLAY,
+};
+
+pub use rom::{load_rom_set, RomChip, RomSource};
+
+pub use io::{CallbackIo, PortIo};
 
 pub use condition_codes::ConditionCodes;
 
-pub use clock_cycles::cycles::CLOCK_CYCLES;
+pub use clock::{BusyWait, Clock, Pacer, Sleep};
+
+pub use clock_cycles::cycles::{
+    CLOCK_CYCLES, CLOCK_CYCLES_8085, CLOCK_CYCLES_NOT_TAKEN, CLOCK_CYCLES_NOT_TAKEN_8085,
+};
 
-#[cfg(not(feature = "cpu_diag"))]
 #[repr(C)]
 pub struct IoCallbacks {
     /// IN port, pass port number back to app
@@ -33,16 +82,19 @@ pub struct IoCallbacks {
     pub output: extern "C" fn(io_object: *const c_void, port: u8, value: u8),
 }
 
-#[cfg(not(feature = "cpu_diag"))]
 #[repr(C)]
 pub struct CpuSender {
     cpu: *mut Cpu8080,
     sender: *mut Sender<Message>,
 }
 
-#[cfg(not(feature = "cpu_diag"))]
+/// Called once a `SaveState`/`LoadState` message is handled,
+/// `size` is the number of bytes written/read, 0 on failure
+pub type StateCompletion = extern "C" fn(context: *const c_void, size: usize);
+
 #[repr(C)]
 pub enum Message {
+    /// RST `irq_no`, 0 to 7, others are dropped
     Interrupt {
         irq_no: u8,
         allow_nested_interrupt: bool,
@@ -50,12 +102,113 @@ pub enum Message {
     Suspend,
     Restart,
     Shutdown,
+    /// Change the clock rate in Hz, 0 runs unthrottled
+    SetFrequency {
+        frequency: u64,
+    },
+    /// Serialize the machine into `buffer`, which
+    /// must stay valid until `completion` is called
+    SaveState {
+        buffer: *mut u8,
+        len: usize,
+        completion: StateCompletion,
+        context: *const c_void,
+    },
+    /// Restore the machine from `buffer`, which
+    /// must stay valid until `completion` is called
+    LoadState {
+        buffer: *const u8,
+        len: usize,
+        completion: StateCompletion,
+        context: *const c_void,
+    },
+    /// Any instruction placed on the bus by the interrupting
+    /// device, e.g. a 3-byte CALL, unused bytes are ignored
+    InterruptInstruction {
+        instruction: [u8; 3],
+        allow_nested_interrupt: bool,
+    },
+    /// Drive an interrupt input of the 8085, ignored by the 8080
+    SetInterruptPin {
+        pin: InterruptPin,
+        level: bool,
+    },
+}
+
+/// The buffers carried by the save/load messages are owned by
+/// the app, which keeps them alive until the completion is called
+unsafe impl Send for Message {}
+
+/// Called at the end of every slice of the run loop with the real
+/// hardware time of the slice and the time spent emulating it, both
+/// in microseconds, the app returns when the next slice is due
+pub type HostPacer = extern "C" fn(context: *const c_void, emulated_us: u64, elapsed_us: u64);
+
+#[repr(C)]
+pub enum Pacing {
+    Sleep,
+    BusyWait,
+    HostDriven,
+}
+
+/// One chip of a ROM set, loaded either from the file at
+/// `path` or, when `path` is null, from `data` of `len` bytes
+#[repr(C)]
+pub struct RomEntry {
+    pub path: *const c_char,
+    pub data: *const u8,
+    pub len: usize,
+    pub load_address: u16,
+    /// Expected size in bytes, 0 to skip the check
+    pub expected_size: usize,
+    pub expected_crc32: u32,
+    pub check_crc32: bool,
+}
+
+unsafe fn path_from(path: *const c_char) -> Result<PathBuf> {
+    if path.is_null() {
+        return Err(EmulatorErrors::InvalidArgument("rom path is null"));
+    }
+    let path = CStr::from_ptr(path)
+        .to_str()
+        .map_err(|_| EmulatorErrors::InvalidArgument("rom path is not valid UTF-8"))?;
+    Ok(PathBuf::from(path))
+}
+
+unsafe fn bytes_from(data: *const u8, len: usize) -> Result<Vec<u8>> {
+    if data.is_null() {
+        return Err(EmulatorErrors::InvalidArgument("rom data is null"));
+    }
+    Ok(slice::from_raw_parts(data, len).to_vec())
+}
+
+fn new_cpu_sender(
+    load_rom: impl FnOnce() -> Result<Vec<u8>>,
+    ram_size: usize,
+    callbacks: IoCallbacks,
+    io_object: *const c_void,
+) -> CpuSender {
+    catch_errors(|| {
+        let (cpu, sender) = Cpu8080::new(
+            load_rom()?,
+            vec![0; ram_size],
+            CallbackIo::new(callbacks, io_object),
+        );
+        Ok(CpuSender {
+            cpu: Box::into_raw(Box::new(cpu)),
+            sender: Box::into_raw(Box::new(sender)),
+        })
+    })
+    .unwrap_or(CpuSender {
+        cpu: ptr::null_mut(),
+        sender: ptr::null_mut(),
+    })
 }
 
 /// # Safety
 /// This function should be called with valid rom path
-/// and the RAM will be allocated on the fly
-#[cfg(not(feature = "cpu_diag"))]
+/// and the RAM will be allocated on the fly.
+/// Both pointers are null if the CPU cannot be created.
 #[no_mangle]
 pub unsafe extern "C" fn new_cpu_instance(
     rom_path: *const c_char,
@@ -63,17 +216,68 @@ pub unsafe extern "C" fn new_cpu_instance(
     callbacks: IoCallbacks,
     io_object: *const c_void,
 ) -> CpuSender {
-    let rom_path = CStr::from_ptr(rom_path);
-    let rom_path = PathBuf::from_str(rom_path.to_str().unwrap()).unwrap();
-    let rom = BufReader::new(File::open(rom_path).unwrap())
-        .bytes()
-        .collect::<std::result::Result<Vec<u8>, std::io::Error>>()
-        .unwrap();
-    let (cpu, sender) = Cpu8080::new(rom, vec![0; ram_size], callbacks, io_object);
-    CpuSender {
-        cpu: Box::into_raw(Box::new(cpu)),
-        sender: Box::into_raw(Box::new(sender)),
-    }
+    new_cpu_sender(
+        || Ok(std::fs::read(path_from(rom_path)?)?),
+        ram_size,
+        callbacks,
+        io_object,
+    )
+}
+
+/// # Safety
+/// `rom` needs to be valid for `rom_len` bytes, it is copied
+/// so the app can release it once this function returns.
+/// Both pointers are null if the CPU cannot be created.
+#[no_mangle]
+pub unsafe extern "C" fn new_cpu_instance_from_bytes(
+    rom: *const u8,
+    rom_len: usize,
+    ram_size: usize,
+    callbacks: IoCallbacks,
+    io_object: *const c_void,
+) -> CpuSender {
+    new_cpu_sender(|| bytes_from(rom, rom_len), ram_size, callbacks, io_object)
+}
+
+/// # Safety
+/// `entries` needs to point to `count` valid entries, the chips
+/// are assembled into one ROM image, see `load_rom_set`.
+/// Both pointers are null if the CPU cannot be created.
+#[no_mangle]
+pub unsafe extern "C" fn new_cpu_instance_from_rom_set(
+    entries: *const RomEntry,
+    count: usize,
+    ram_size: usize,
+    callbacks: IoCallbacks,
+    io_object: *const c_void,
+) -> CpuSender {
+    new_cpu_sender(
+        || {
+            if entries.is_null() {
+                return Err(EmulatorErrors::InvalidArgument("rom entries are null"));
+            }
+            let chips = slice::from_raw_parts(entries, count)
+                .iter()
+                .map(|entry| {
+                    let source = if entry.path.is_null() {
+                        RomSource::Bytes(bytes_from(entry.data, entry.len)?)
+                    } else {
+                        RomSource::File(path_from(entry.path)?)
+                    };
+                    Ok(RomChip {
+                        source,
+                        load_address: entry.load_address,
+                        size: (entry.expected_size != 0).then_some(entry.expected_size),
+                        crc32: entry.check_crc32.then_some(entry.expected_crc32),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            load_rom_set(&chips)
+        },
+        ram_size,
+        callbacks,
+        io_object,
+    )
 }
 
 /// # Safety
@@ -81,26 +285,465 @@ pub unsafe extern "C" fn new_cpu_instance(
 /// Send a `Shutdown` message can break the loop, so
 /// that the CPU and the Sender will be dropped, this is
 /// the only way to release the resources to the system.
-#[cfg(not(feature = "cpu_diag"))]
+/// They are dropped as well when the loop fails, the
+/// error is reported on the thread calling `run`.
 #[no_mangle]
-pub unsafe extern "C" fn run(cpu: *mut Cpu8080, sender: *mut Sender<Message>) {
-    let _sender = Box::from_raw(sender);
-    Box::from_raw(cpu).run().unwrap();
+pub unsafe extern "C" fn run(cpu: *mut Cpu8080, sender: *mut Sender<Message>) -> EmulatorStatus {
+    status_of(|| {
+        if cpu.is_null() || sender.is_null() {
+            return Err(EmulatorErrors::InvalidArgument("CPU or sender is null"));
+        }
+        let _sender = Box::from_raw(sender);
+        Box::from_raw(cpu).run()
+    })
 }
 
 /// # Safety
 /// This function should be safe for accessing video ram.
-#[cfg(not(feature = "cpu_diag"))]
 #[no_mangle]
 pub unsafe extern "C" fn get_ram(cpu: *const Cpu8080) -> *const u8 {
-    (*cpu).get_ram().as_ptr()
+    match cpu.as_ref() {
+        Some(cpu) => cpu.get_ram().as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+unsafe fn cpu_ref<'a>(cpu: *const Cpu8080) -> Result<&'a Cpu8080> {
+    cpu.as_ref()
+        .ok_or(EmulatorErrors::InvalidArgument("CPU is null"))
+}
+
+unsafe fn cpu_mut<'a>(cpu: *mut Cpu8080) -> Result<&'a mut Cpu8080> {
+    cpu.as_mut()
+        .ok_or(EmulatorErrors::InvalidArgument("CPU is null"))
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call
+/// this while the `run` loop owns the CPU.
+#[no_mangle]
+pub unsafe extern "C" fn get_cpu_state(
+    cpu: *const Cpu8080,
+    state: *mut CpuState,
+) -> EmulatorStatus {
+    status_of(|| {
+        let state = state
+            .as_mut()
+            .ok_or(EmulatorErrors::InvalidArgument("state is null"))?;
+        *state = cpu_ref(cpu)?.state();
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call
+/// this while the `run` loop owns the CPU.
+#[no_mangle]
+pub unsafe extern "C" fn set_cpu_state(cpu: *mut Cpu8080, state: CpuState) -> EmulatorStatus {
+    status_of(|| {
+        cpu_mut(cpu)?.set_state(&state);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call this while the
+/// `run` loop owns the CPU, send `SetFrequency` instead.
+/// `pacer` and `context` are only used with `HostDriven` pacing.
+#[no_mangle]
+pub unsafe extern "C" fn set_clock(
+    cpu: *mut Cpu8080,
+    frequency: u64,
+    slice_cycles: u64,
+    pacing: Pacing,
+    pacer: Option<HostPacer>,
+    context: *const c_void,
+) -> EmulatorStatus {
+    status_of(|| {
+        let clock = Clock::new(frequency).slice_cycles(slice_cycles);
+        let clock = match pacing {
+            Pacing::Sleep => clock.pacer(Sleep),
+            Pacing::BusyWait => clock.pacer(BusyWait),
+            Pacing::HostDriven => {
+                let pacer = pacer.ok_or(EmulatorErrors::InvalidArgument("pacer is null"))?;
+                clock.pacer(move |emulated: Duration, elapsed: Duration| {
+                    pacer(
+                        context,
+                        emulated.as_micros() as u64,
+                        elapsed.as_micros() as u64,
+                    )
+                })
+            }
+        };
+        cpu_mut(cpu)?.set_clock(clock);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call
+/// this while the `run` loop owns the CPU.
+#[no_mangle]
+pub unsafe extern "C" fn set_variant(cpu: *mut Cpu8080, variant: Variant) -> EmulatorStatus {
+    status_of(|| {
+        cpu_mut(cpu)?.set_variant(variant);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call this while the
+/// `run` loop owns the CPU, send `SetInterruptPin` instead.
+#[no_mangle]
+pub unsafe extern "C" fn set_interrupt_pin(
+    cpu: *mut Cpu8080,
+    pin: InterruptPin,
+    level: bool,
+) -> EmulatorStatus {
+    status_of(|| cpu_mut(cpu)?.set_interrupt_pin(pin, level))
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call
+/// this while the `run` loop owns the CPU.
+#[no_mangle]
+pub unsafe extern "C" fn set_sid(cpu: *mut Cpu8080, level: bool) -> EmulatorStatus {
+    status_of(|| cpu_mut(cpu)?.set_sid(level))
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call this while
+/// the `run` loop owns the CPU. False if `cpu` is null.
+#[no_mangle]
+pub unsafe extern "C" fn get_sod(cpu: *const Cpu8080) -> bool {
+    cpu.as_ref().is_some_and(|cpu| cpu.sod())
+}
+
+/// Called with every line of the execution trace, see `set_tracer`.
+/// The line is only valid during the call.
+pub type TraceCallback = extern "C" fn(context: *const c_void, line: *const c_char);
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call this while the
+/// `run` loop owns the CPU. `callback` receives a line in `format`
+/// before every instruction, a null `callback` stops tracing.
+#[no_mangle]
+pub unsafe extern "C" fn set_tracer(
+    cpu: *mut Cpu8080,
+    format: TraceFormat,
+    callback: Option<TraceCallback>,
+    context: *const c_void,
+) -> EmulatorStatus {
+    status_of(|| {
+        let cpu = cpu_mut(cpu)?;
+        match callback {
+            Some(callback) => cpu.set_tracer(move |record: &TraceRecord| {
+                // the line has no nul byte
+                let line = CString::new(record.format(format)).unwrap_or_default();
+                callback(context, line.as_ptr())
+            }),
+            None => drop(cpu.remove_tracer()),
+        }
+        Ok(())
+    })
+}
+
+/// Called from the thread running the CPU when it halts with interrupts
+/// disabled, the run loop then waits for a message such as `Restart`
+pub type HaltCallback = extern "C" fn(context: *const c_void);
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call this while
+/// the `run` loop owns the CPU. A null `callback` removes it.
+#[no_mangle]
+pub unsafe extern "C" fn set_halt_callback(
+    cpu: *mut Cpu8080,
+    callback: Option<HaltCallback>,
+    context: *const c_void,
+) -> EmulatorStatus {
+    status_of(|| {
+        let cpu = cpu_mut(cpu)?;
+        match callback {
+            Some(callback) => cpu.set_halt_handler(move || callback(context)),
+            None => drop(cpu.remove_halt_handler()),
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call this while
+/// the `run` loop owns the CPU. RST `irq_no` fires once after
+/// `delay_cycles`, then every `period_cycles` unless it is 0.
+/// The id to cancel the event is set to `id` if not null.
+#[no_mangle]
+pub unsafe extern "C" fn schedule_interrupt(
+    cpu: *mut Cpu8080,
+    delay_cycles: u64,
+    period_cycles: u64,
+    irq_no: u8,
+    allow_nested_interrupt: bool,
+    id: *mut u64,
+) -> EmulatorStatus {
+    status_of(|| {
+        let action = EventAction::Interrupt {
+            instruction: [rst_instruction(irq_no)?, 0, 0],
+            allow_nested_interrupt,
+        };
+        let period = (period_cycles != 0).then_some(period_cycles);
+        let event = cpu_mut(cpu)?.schedule(delay_cycles, period, action)?;
+        if let Some(id) = id.as_mut() {
+            *id = event.0
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, do not call
+/// this while the `run` loop owns the CPU.
+#[no_mangle]
+pub unsafe extern "C" fn cancel_event(cpu: *mut Cpu8080, id: u64) -> EmulatorStatus {
+    status_of(|| {
+        if !cpu_mut(cpu)?.cancel(EventId(id)) {
+            return Err(EmulatorErrors::InvalidArgument("no such event"));
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The CPU instance needs to be valid, 0 if it is null.
+#[no_mangle]
+pub unsafe extern "C" fn save_state_size(cpu: *const Cpu8080) -> usize {
+    cpu.as_ref().map_or(0, |cpu| cpu.save_state_size())
+}
+
+/// # Safety
+/// `buffer` needs to be valid for `len` bytes, do not call this
+/// while the `run` loop owns the CPU, send `SaveState` instead.
+/// The number of bytes written is set to `written`.
+#[no_mangle]
+pub unsafe extern "C" fn save_state(
+    cpu: *const Cpu8080,
+    buffer: *mut u8,
+    len: usize,
+    written: *mut usize,
+) -> EmulatorStatus {
+    status_of(|| {
+        if buffer.is_null() || written.is_null() {
+            return Err(EmulatorErrors::InvalidArgument("buffer is null"));
+        }
+        let state = cpu_ref(cpu)?.save_state();
+        if state.len() > len {
+            return Err(EmulatorErrors::InvalidArgument("buffer is too small"));
+        }
+        std::slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+        *written = state.len();
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `buffer` needs to be valid for `len` bytes, do not call this
+/// while the `run` loop owns the CPU, send `LoadState` instead.
+#[no_mangle]
+pub unsafe extern "C" fn load_state(
+    cpu: *mut Cpu8080,
+    buffer: *const u8,
+    len: usize,
+) -> EmulatorStatus {
+    status_of(|| {
+        if buffer.is_null() {
+            return Err(EmulatorErrors::InvalidArgument("buffer is null"));
+        }
+        cpu_mut(cpu)?.load_state(std::slice::from_raw_parts(buffer, len))
+    })
 }
 
 /// # Safety
 /// Sender needs to be present(not dropped) for
 /// sending the messages to the CPU instance.
-#[cfg(not(feature = "cpu_diag"))]
 #[no_mangle]
-pub unsafe extern "C" fn send_message(sender: *const Sender<Message>, message: Message) {
-    (*sender).send(message).unwrap()
+pub unsafe extern "C" fn send_message(
+    sender: *const Sender<Message>,
+    message: Message,
+) -> EmulatorStatus {
+    status_of(|| {
+        let sender = sender
+            .as_ref()
+            .ok_or(EmulatorErrors::InvalidArgument("sender is null"))?;
+        if let Message::Interrupt { irq_no, .. } = message {
+            rst_instruction(irq_no)?;
+        }
+        sender
+            .send(message)
+            .map_err(|_| EmulatorErrors::Disconnected)
+    })
+}
+
+/// # Safety
+/// `ram` needs to be valid for `ram_len` bytes, e.g. from `get_ram`,
+/// `overlay` for `overlay_len` bands (null if none), and `out` for
+/// `out_len` bytes, at least `frame_buffer_size`. Do not call this
+/// while the `run` loop writes the RAM without synchronization.
+#[no_mangle]
+pub unsafe extern "C" fn decode_frame_into(
+    ram: *const u8,
+    ram_len: usize,
+    layout: VideoLayout,
+    overlay: *const ColorBand,
+    overlay_len: usize,
+    format: PixelFormat,
+    out: *mut u8,
+    out_len: usize,
+) -> EmulatorStatus {
+    status_of(|| {
+        if ram.is_null() || out.is_null() {
+            return Err(EmulatorErrors::InvalidArgument("buffer is null"));
+        }
+        let overlay = match overlay.is_null() {
+            true => &[],
+            false => slice::from_raw_parts(overlay, overlay_len),
+        };
+        decode_frame(
+            slice::from_raw_parts(ram, ram_len),
+            &layout,
+            overlay,
+            format,
+            slice::from_raw_parts_mut(out, out_len),
+        )
+    })
+}
+
+/// Bytes needed by `decode_frame_into`
+#[no_mangle]
+pub extern "C" fn frame_buffer_size(layout: VideoLayout, format: PixelFormat) -> usize {
+    layout.buffer_size(format)
+}
+
+/// The layout of the video RAM returned by `get_ram` for Space
+/// Invaders, pass 0 as `offset` for `space_invaders_framebuffer`
+#[no_mangle]
+pub extern "C" fn space_invaders_video_layout(offset: usize) -> VideoLayout {
+    VideoLayout {
+        offset,
+        ..VideoLayout::SPACE_INVADERS
+    }
+}
+
+/// The cellophane bands of the Space Invaders cabinet,
+/// `count` is set to the number of bands
+///
+/// # Safety
+/// `count` needs to be valid
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_overlay(count: *mut usize) -> *const ColorBand {
+    if let Some(count) = count.as_mut() {
+        *count = SPACE_INVADERS_OVERLAY.len()
+    }
+    SPACE_INVADERS_OVERLAY.as_ptr()
+}
+
+/// Called for every sound change since the last call,
+/// see `space_invaders_take_sound_events`
+pub type SoundCallback = extern "C" fn(context: *const c_void, event: SoundEvent);
+
+/// # Safety
+/// `rom` needs to be valid for `rom_len` bytes, up to 8 KiB, it
+/// is copied so the app can release it once this function returns.
+/// Null if the machine cannot be created, release it with
+/// `space_invaders_free`.
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_new(rom: *const u8, rom_len: usize) -> *mut SpaceInvaders {
+    catch_errors(|| {
+        let machine = SpaceInvaders::new(bytes_from(rom, rom_len)?)?;
+        Ok(Box::into_raw(Box::new(machine)))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// # Safety
+/// The machine needs to come from `space_invaders_new`,
+/// it must not be used anymore after this call.
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_free(machine: *mut SpaceInvaders) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine))
+    }
+}
+
+unsafe fn machine_mut<'a>(machine: *mut SpaceInvaders) -> Result<&'a mut SpaceInvaders> {
+    machine
+        .as_mut()
+        .ok_or(EmulatorErrors::InvalidArgument("machine is null"))
+}
+
+/// # Safety
+/// The machine needs to be valid. Emulates 1/60 second,
+/// call it on every vsync of the display.
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_run_frame(machine: *mut SpaceInvaders) -> EmulatorStatus {
+    status_of(|| machine_mut(machine)?.run_frame())
+}
+
+/// # Safety
+/// The machine needs to be valid.
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_set_button(
+    machine: *mut SpaceInvaders,
+    button: Button,
+    pressed: bool,
+) -> EmulatorStatus {
+    status_of(|| {
+        machine_mut(machine)?.set_button(button, pressed);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The machine needs to be valid.
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_set_dip_switches(
+    machine: *mut SpaceInvaders,
+    dip_switches: u8,
+) -> EmulatorStatus {
+    status_of(|| {
+        machine_mut(machine)?.set_dip_switches(dip_switches);
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The machine needs to be valid, the `callback` is
+/// called with every sound change, in order.
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_take_sound_events(
+    machine: *mut SpaceInvaders,
+    callback: SoundCallback,
+    context: *const c_void,
+) -> EmulatorStatus {
+    status_of(|| {
+        for event in machine_mut(machine)?.take_sound_events() {
+            callback(context, event)
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// The machine needs to be valid, null if it is null.
+/// The framebuffer is `space_invaders_framebuffer_size` bytes,
+/// see `SpaceInvaders::framebuffer` for the layout.
+#[no_mangle]
+pub unsafe extern "C" fn space_invaders_framebuffer(machine: *const SpaceInvaders) -> *const u8 {
+    match machine.as_ref() {
+        Some(machine) => machine.framebuffer().as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn space_invaders_framebuffer_size() -> usize {
+    FRAMEBUFFER_SIZE
 }
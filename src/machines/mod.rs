@@ -0,0 +1,3 @@
+//! Complete boards built on `Cpu8080`, ready to be driven frame by frame
+
+pub mod space_invaders;
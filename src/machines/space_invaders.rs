@@ -0,0 +1,380 @@
+//! The Taito 8080 board running Space Invaders: 8 KiB of ROM,
+//! 1 KiB of work RAM followed by 7 KiB of video RAM, a hardware
+//! bit shifter, the cabinet inputs, the sound latches and two
+//! interrupts per frame (RST 1 mid-screen, RST 2 at vblank).
+
+use std::mem;
+
+use crate::{rst_instruction, Cpu8080, EventAction, MemoryBus, PortIo, Result, RomError};
+
+/// The board runs at 2 MHz
+pub const CLOCK_FREQUENCY: u64 = 2_000_000;
+
+/// The monitor refreshes at 60 Hz
+pub const CYCLES_PER_FRAME: u64 = CLOCK_FREQUENCY / 60;
+
+pub const ROM_SIZE: usize = 0x2000;
+
+pub const RAM_SIZE: usize = 0x2000;
+
+/// Where the video RAM starts in the address space
+pub const VRAM_START: u16 = 0x2400;
+
+/// 256x224 pixels, 1 bit each
+pub const FRAMEBUFFER_SIZE: usize = 256 * 224 / 8;
+
+/// Address lines A14 and A15 are not decoded, the
+/// 16 KiB of ROM and RAM repeat all over the address space
+pub struct InvadersMemory {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl InvadersMemory {
+    /// `rom` is the 8 KiB image of invaders.h, .g, .f and .e,
+    /// a shorter one is padded with zeros
+    pub fn new(mut rom: Vec<u8>) -> Result<Self> {
+        if rom.len() > ROM_SIZE {
+            return Err(RomError::SizeMismatch {
+                load_address: 0,
+                expected: ROM_SIZE,
+                actual: rom.len(),
+            }
+            .into());
+        }
+        rom.resize(ROM_SIZE, 0);
+        Ok(InvadersMemory {
+            rom,
+            ram: vec![0; RAM_SIZE],
+        })
+    }
+}
+
+impl MemoryBus for InvadersMemory {
+    fn read(&self, addr: u16) -> Result<u8> {
+        let addr = addr as usize & 0x3fff;
+        Ok(match addr.checked_sub(ROM_SIZE) {
+            Some(addr) => self.ram[addr],
+            None => self.rom[addr],
+        })
+    }
+
+    fn write(&mut self, addr: u16, value: u8) -> Result<()> {
+        if let Some(addr) = (addr as usize & 0x3fff).checked_sub(ROM_SIZE) {
+            self.ram[addr] = value
+        }
+        Ok(())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+}
+
+/// The cabinet controls, active while pressed
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Coin,
+    P1Start,
+    P2Start,
+    P1Shot,
+    P1Left,
+    P1Right,
+    P2Shot,
+    P2Left,
+    P2Right,
+    Tilt,
+}
+
+impl Button {
+    /// Input port and bit the button is wired to
+    fn wiring(self) -> (u8, u8) {
+        match self {
+            Button::Coin => (1, 0),
+            Button::P2Start => (1, 1),
+            Button::P1Start => (1, 2),
+            Button::P1Shot => (1, 4),
+            Button::P1Left => (1, 5),
+            Button::P1Right => (1, 6),
+            Button::Tilt => (2, 2),
+            Button::P2Shot => (2, 4),
+            Button::P2Left => (2, 5),
+            Button::P2Right => (2, 6),
+        }
+    }
+}
+
+/// The sounds triggered through the latches on ports 3 and 5
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    /// Loops while the UFO is on screen
+    Ufo,
+    Shot,
+    PlayerDie,
+    InvaderDie,
+    ExtendedPlay,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+const PORT3_SOUNDS: [Sound; 5] = [
+    Sound::Ufo,
+    Sound::Shot,
+    Sound::PlayerDie,
+    Sound::InvaderDie,
+    Sound::ExtendedPlay,
+];
+
+const PORT5_SOUNDS: [Sound; 5] = [
+    Sound::Fleet1,
+    Sound::Fleet2,
+    Sound::Fleet3,
+    Sound::Fleet4,
+    Sound::UfoHit,
+];
+
+/// A sound latch bit has changed, `playing` is
+/// true when it starts and false when it stops
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoundEvent {
+    pub sound: Sound,
+    pub playing: bool,
+}
+
+/// The devices behind `IN` and `OUT` on the board
+pub struct SpaceInvadersIo {
+    inputs: [u8; 3],
+    dip_switches: u8,
+    shift_register: u16,
+    shift_offset: u8,
+    sound_latches: [u8; 2],
+    sound_events: Vec<SoundEvent>,
+    watchdog_kicks: u64,
+}
+
+impl Default for SpaceInvadersIo {
+    fn default() -> Self {
+        SpaceInvadersIo {
+            // bits wired high on the board
+            inputs: [0x0e, 0x08, 0x00],
+            dip_switches: 0,
+            shift_register: 0,
+            shift_offset: 0,
+            sound_latches: [0; 2],
+            sound_events: vec![],
+            watchdog_kicks: 0,
+        }
+    }
+}
+
+impl SpaceInvadersIo {
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let (port, bit) = button.wiring();
+        let input = &mut self.inputs[port as usize];
+        if pressed {
+            *input |= 1 << bit
+        } else {
+            *input &= !(1 << bit)
+        }
+    }
+
+    /// Port 2 switches: bits 0-1 are the ships per game minus 3,
+    /// bit 3 gives the extra ship at 1000 points instead of 1500,
+    /// bit 7 hides the coin info in the demo, other bits are ignored
+    pub fn set_dip_switches(&mut self, dip_switches: u8) {
+        self.dip_switches = dip_switches & 0x8b
+    }
+
+    /// Sound changes since the last call, in the order they happened
+    pub fn take_sound_events(&mut self) -> Vec<SoundEvent> {
+        mem::take(&mut self.sound_events)
+    }
+
+    /// How many times the game has reset the watchdog on port 6,
+    /// the board resets the CPU if it stops doing so, which is not
+    /// emulated as a correct program never lets it happen
+    pub fn watchdog_kicks(&self) -> u64 {
+        self.watchdog_kicks
+    }
+
+    fn latch_sounds(&mut self, latch: usize, value: u8, sounds: &[Sound; 5]) {
+        let changed = self.sound_latches[latch] ^ value;
+        self.sound_latches[latch] = value;
+        for (bit, &sound) in sounds.iter().enumerate() {
+            if changed & 1 << bit != 0 {
+                self.sound_events.push(SoundEvent {
+                    sound,
+                    playing: value & 1 << bit != 0,
+                })
+            }
+        }
+    }
+}
+
+impl PortIo for SpaceInvadersIo {
+    fn input(&mut self, port: u8) -> u8 {
+        match port {
+            0 | 1 => self.inputs[port as usize],
+            2 => self.inputs[2] | self.dip_switches,
+            // the shifter outputs 8 of its 16 bits, `shift_offset` from the top
+            3 => (self.shift_register >> (8 - self.shift_offset)) as u8,
+            _ => 0,
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            2 => self.shift_offset = value & 0x07,
+            3 => self.latch_sounds(0, value, &PORT3_SOUNDS),
+            4 => self.shift_register = (value as u16) << 8 | self.shift_register >> 8,
+            5 => self.latch_sounds(1, value, &PORT5_SOUNDS),
+            6 => self.watchdog_kicks += 1,
+            _ => (),
+        }
+    }
+}
+
+/// The whole board, driven by the host one frame at a time
+pub struct SpaceInvaders {
+    cpu: Cpu8080<InvadersMemory, SpaceInvadersIo>,
+    /// Cycle count at which the current frame ends
+    frame_end: u64,
+}
+
+impl SpaceInvaders {
+    pub fn new(rom: Vec<u8>) -> Result<Self> {
+        let (mut cpu, _) =
+            Cpu8080::with_memory(InvadersMemory::new(rom)?, SpaceInvadersIo::default());
+        let rst = |irq_no| -> Result<_> {
+            Ok(EventAction::Interrupt {
+                instruction: [rst_instruction(irq_no)?, 0, 0],
+                allow_nested_interrupt: false,
+            })
+        };
+        cpu.schedule(CYCLES_PER_FRAME / 2, Some(CYCLES_PER_FRAME), rst(1)?)?;
+        cpu.schedule(CYCLES_PER_FRAME, Some(CYCLES_PER_FRAME), rst(2)?)?;
+        Ok(SpaceInvaders { cpu, frame_end: 0 })
+    }
+
+    /// Emulate 1/60 second, the vblank interrupt ends the frame,
+    /// which leaves the picture complete in the framebuffer
+    pub fn run_frame(&mut self) -> Result<()> {
+        self.frame_end += CYCLES_PER_FRAME;
+        let remaining = self.frame_end.saturating_sub(self.cpu.cycles());
+        self.cpu.run_for_cycles(remaining)?;
+        Ok(())
+    }
+
+    /// The video RAM: 224 columns of 256 pixels from bottom
+    /// to top, 8 pixels per byte with the least significant
+    /// bit lowest, as the monitor is rotated in the cabinet
+    pub fn framebuffer(&self) -> &[u8] {
+        let start = VRAM_START as usize - ROM_SIZE;
+        &self.cpu.memory().ram()[start..start + FRAMEBUFFER_SIZE]
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.io_mut().set_button(button, pressed)
+    }
+
+    pub fn set_dip_switches(&mut self, dip_switches: u8) {
+        self.cpu.io_mut().set_dip_switches(dip_switches)
+    }
+
+    pub fn take_sound_events(&mut self) -> Vec<SoundEvent> {
+        self.cpu.io_mut().take_sound_events()
+    }
+
+    pub fn cpu(&self) -> &Cpu8080<InvadersMemory, SpaceInvadersIo> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu8080<InvadersMemory, SpaceInvadersIo> {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift_register() {
+        let mut io = SpaceInvadersIo::default();
+        io.output(4, 0xab);
+        io.output(4, 0xcd);
+        assert_eq!(io.input(3), 0xcd);
+        io.output(2, 4);
+        assert_eq!(io.input(3), 0xda);
+        io.output(2, 0x0f);
+        assert_eq!(io.input(3), 0xd5);
+    }
+
+    #[test]
+    fn inputs_and_sounds() {
+        let mut io = SpaceInvadersIo::default();
+        io.set_button(Button::Coin, true);
+        io.set_button(Button::P2Left, true);
+        io.set_dip_switches(0xff);
+        assert_eq!(io.input(1), 0x09);
+        assert_eq!(io.input(2), 0xab);
+        io.set_button(Button::Coin, false);
+        assert_eq!(io.input(1), 0x08);
+
+        io.output(3, 0x03);
+        io.output(3, 0x02);
+        io.output(5, 0x10);
+        assert_eq!(
+            io.take_sound_events(),
+            [
+                (Sound::Ufo, true),
+                (Sound::Shot, true),
+                (Sound::Ufo, false),
+                (Sound::UfoHit, true)
+            ]
+            .map(|(sound, playing)| SoundEvent { sound, playing })
+        );
+        assert!(io.take_sound_events().is_empty());
+    }
+
+    #[test]
+    fn frame_interrupts() {
+        // LXI SP,2400H; EI; JMP 0004H, RST 1 does INR B, RST 2 INR C
+        let mut rom = vec![0; 0x13];
+        rom[..7].copy_from_slice(&[0x31, 0x00, 0x24, 0xfb, 0xc3, 0x04, 0x00]);
+        rom[0x08..0x0b].copy_from_slice(&[0x04, 0xfb, 0xc9]);
+        rom[0x10..0x13].copy_from_slice(&[0x0c, 0xfb, 0xc9]);
+        let mut machine = SpaceInvaders::new(rom).unwrap();
+        for _ in 0..3 {
+            machine.run_frame().unwrap();
+        }
+        machine.cpu_mut().run_for_cycles(100).unwrap();
+        let state = machine.cpu().state();
+        assert_eq!((state.reg_b, state.reg_c), (3, 3));
+        assert!(machine.cpu().cycles() >= 3 * CYCLES_PER_FRAME);
+
+        // RAM is mirrored above 0x4000, the ROM is read-only
+        let memory = machine.cpu_mut().memory_mut();
+        memory.write(0x6400, 0xff).unwrap();
+        memory.write(0x0000, 0xff).unwrap();
+        assert_eq!(memory.read(0x2400).unwrap(), 0xff);
+        assert_eq!(memory.read(0x4000).unwrap(), 0x31);
+        assert_eq!(machine.framebuffer().len(), FRAMEBUFFER_SIZE);
+        assert_eq!(machine.framebuffer()[0], 0xff);
+        assert!(SpaceInvaders::new(vec![0; ROM_SIZE + 1]).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{MemoryOutOfBounds, Result};
+
+/// The address space seen by the CPU, every opcode fetch,
+/// operand load and store goes through this bus.
+///
+/// Implement it to describe a board: mirrored RAM, ROM at high
+/// addresses, bank-switched regions or memory-mapped devices.
+pub trait MemoryBus {
+    fn read(&self, addr: u16) -> Result<u8>;
+
+    fn write(&mut self, addr: u16, value: u8) -> Result<()>;
+
+    /// Contiguous RAM exposed to the outside world, e.g. for video RAM
+    /// access. It is all a save state holds of the memory, so it has to
+    /// cover every byte the program can write
+    fn ram(&self) -> &[u8];
+
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    /// Read-only contents, checksummed to validate save states
+    fn rom(&self) -> &[u8];
+
+    /// Called when the CPU is restarted from scratch
+    fn reset(&mut self) {
+        self.ram_mut().fill(0)
+    }
+}
+
+/// The default layout: ROM sits at address 0 and RAM follows it.
+/// Writes to ROM are silently dropped.
+pub struct RomRam {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomRam {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Self {
+        RomRam { rom, ram }
+    }
+}
+
+impl MemoryBus for RomRam {
+    /// It is allowed to load content from either ROM or RAM
+    fn read(&self, addr: u16) -> Result<u8> {
+        let addr = addr as usize;
+        if addr >= self.rom.len() {
+            Ok(*self
+                .ram
+                .get(addr - self.rom.len())
+                .ok_or(MemoryOutOfBounds)?)
+        } else {
+            Ok(self.rom[addr])
+        }
+    }
+
+    /// It is only allowed to write to RAM, we shall never write to ROM
+    fn write(&mut self, addr: u16, value: u8) -> Result<()> {
+        if let Some(content) = (addr as usize)
+            .checked_sub(self.rom.len())
+            .and_then(|addr| self.ram.get_mut(addr))
+        {
+            *content = value
+        }
+        Ok(())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+}
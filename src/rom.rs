@@ -0,0 +1,128 @@
+use std::{fs, path::PathBuf};
+
+use crate::{checksum::crc32, Result, RomError};
+
+pub enum RomSource {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+/// One chip of a ROM set, e.g. Space Invaders ships
+/// invaders.h/g/f/e loaded at 0x0000/0x0800/0x1000/0x1800
+pub struct RomChip {
+    pub source: RomSource,
+    pub load_address: u16,
+    /// Expected size in bytes, checked when given
+    pub size: Option<usize>,
+    /// Expected CRC-32 of the chip, checked when given
+    pub crc32: Option<u32>,
+}
+
+impl RomChip {
+    pub fn new(source: RomSource, load_address: u16) -> Self {
+        RomChip {
+            source,
+            load_address,
+            size: None,
+            crc32: None,
+        }
+    }
+
+    fn load(&self) -> Result<Vec<u8>> {
+        let data = match &self.source {
+            RomSource::File(path) => fs::read(path)?,
+            RomSource::Bytes(bytes) => bytes.clone(),
+        };
+        if let Some(expected) = self.size {
+            if data.len() != expected {
+                return Err(RomError::SizeMismatch {
+                    load_address: self.load_address,
+                    expected,
+                    actual: data.len(),
+                }
+                .into());
+            }
+        }
+        if let Some(expected) = self.crc32 {
+            let actual = crc32(&data);
+            if actual != expected {
+                return Err(RomError::ChecksumMismatch {
+                    load_address: self.load_address,
+                    expected,
+                    actual,
+                }
+                .into());
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Assemble the chips into one ROM image starting at address 0,
+/// gaps between the chips are filled with 0
+pub fn load_rom_set(chips: &[RomChip]) -> Result<Vec<u8>> {
+    let mut rom = vec![];
+    let mut loaded = vec![false; 0x10000];
+    for chip in chips {
+        let data = chip.load()?;
+        let start = chip.load_address as usize;
+        let end = start + data.len();
+        if end > loaded.len() {
+            return Err(RomError::TooLarge {
+                load_address: chip.load_address,
+            }
+            .into());
+        }
+        if loaded[start..end].iter().any(|&loaded| loaded) {
+            return Err(RomError::Overlap {
+                load_address: chip.load_address,
+            }
+            .into());
+        }
+        loaded[start..end].fill(true);
+        if rom.len() < end {
+            rom.resize(end, 0);
+        }
+        rom[start..end].copy_from_slice(&data);
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmulatorErrors;
+
+    #[test]
+    fn chips_at_offsets() {
+        let mut high = RomChip::new(RomSource::Bytes(vec![3, 4]), 0x0004);
+        high.size = Some(2);
+        high.crc32 = Some(crc32(&[3, 4]));
+        let low = RomChip::new(RomSource::Bytes(vec![1, 2]), 0x0000);
+        assert_eq!(load_rom_set(&[high, low]).unwrap(), [1, 2, 0, 0, 3, 4]);
+    }
+
+    #[test]
+    fn invalid_chips() {
+        let mut chip = RomChip::new(RomSource::Bytes(vec![1, 2]), 0);
+        chip.crc32 = Some(0);
+        assert!(matches!(
+            load_rom_set(&[chip]),
+            Err(EmulatorErrors::Rom(RomError::ChecksumMismatch { .. }))
+        ));
+        let mut chip = RomChip::new(RomSource::Bytes(vec![1, 2]), 0);
+        chip.size = Some(0x800);
+        assert!(matches!(
+            load_rom_set(&[chip]),
+            Err(EmulatorErrors::Rom(RomError::SizeMismatch { .. }))
+        ));
+        let chips = [
+            RomChip::new(RomSource::Bytes(vec![1, 2]), 0),
+            RomChip::new(RomSource::Bytes(vec![3]), 1),
+        ];
+        assert!(matches!(
+            load_rom_set(&chips),
+            Err(EmulatorErrors::Rom(RomError::Overlap { load_address: 1 }))
+        ));
+    }
+}
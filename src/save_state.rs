@@ -0,0 +1,216 @@
+use crate::{
+    checksum::crc32, cpu::Pins8085, Cpu8080, CpuState, MemoryBus, PortIo, Result, SaveStateError,
+};
+
+const MAGIC: &[u8; 4] = b"I80S";
+
+/// Bumped whenever the layout below changes,
+/// states of other versions are refused
+pub const SAVE_STATE_VERSION: u8 = 5;
+
+/// All little endian:
+/// magic(4) version(1) rom_crc32(4) variant(1)
+/// reg_a..reg_l(7) flags(1, PUSH PSW layout) sp(2) pc(2) interrupt_enabled(1) halted(1)
+/// interrupt_delayed(1) interrupt_request(1, 0 if none) instruction(3) allow_nested_interrupt(1)
+/// pins(6, see `Pins8085::to_bytes`)
+/// ram_len(4) ram(ram_len)
+const HEADER_SIZE: usize = 40;
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(SaveStateError::Truncated.into());
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn take_array<const N: usize>(data: &mut &[u8]) -> Result<[u8; N]> {
+    Ok(take(data, N)?.try_into().unwrap())
+}
+
+impl<M: MemoryBus, P: PortIo> Cpu8080<M, P> {
+    /// # Safety
+    /// `buffer` needs to be valid for writing `len` bytes
+    pub(crate) unsafe fn save_state_into(&self, buffer: *mut u8, len: usize) -> usize {
+        let state = self.save_state();
+        if state.len() > len {
+            return 0;
+        }
+        std::slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+        state.len()
+    }
+
+    /// # Safety
+    /// `buffer` needs to be valid for reading `len` bytes
+    pub(crate) unsafe fn load_state_from(&mut self, buffer: *const u8, len: usize) -> usize {
+        match self.load_state(std::slice::from_raw_parts(buffer, len)) {
+            Ok(()) => self.save_state_size(),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn save_state_size(&self) -> usize {
+        HEADER_SIZE + self.memory().ram().len()
+    }
+
+    /// Serialize the registers, flags, interrupt state and RAM,
+    /// along with the ROM checksum used to validate `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let state = self.state();
+        let ram = self.memory().ram();
+        let mut data = Vec::with_capacity(self.save_state_size());
+        data.extend_from_slice(MAGIC);
+        data.push(SAVE_STATE_VERSION);
+        data.extend_from_slice(&crc32(self.memory().rom()).to_le_bytes());
+        data.push(self.variant() as u8);
+        data.extend_from_slice(&[
+            state.reg_a,
+            state.reg_b,
+            state.reg_c,
+            state.reg_d,
+            state.reg_e,
+            state.reg_h,
+            state.reg_l,
+            state.flags,
+        ]);
+        data.extend_from_slice(&state.sp.to_le_bytes());
+        data.extend_from_slice(&state.pc.to_le_bytes());
+        data.push(state.interrupt_enabled as u8);
+        data.push(state.halted as u8);
+        data.push(self.interrupt_delayed as u8);
+        let (instruction, allow_nested_interrupt) = self.interrupt_request.unwrap_or_default();
+        data.push(self.interrupt_request.is_some() as u8);
+        data.extend_from_slice(&instruction);
+        data.push(allow_nested_interrupt as u8);
+        data.extend_from_slice(&self.pins.to_bytes());
+        data.extend_from_slice(&(ram.len() as u32).to_le_bytes());
+        data.extend_from_slice(ram);
+        data
+    }
+
+    /// Restore a state produced by `save_state`, nothing is
+    /// touched unless the whole state is valid for this machine
+    pub fn load_state(&mut self, mut data: &[u8]) -> Result<()> {
+        let data = &mut data;
+        if take(data, MAGIC.len())? != MAGIC {
+            return Err(SaveStateError::BadMagic.into());
+        }
+        let [version] = take_array(data)?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version).into());
+        }
+        if u32::from_le_bytes(take_array(data)?) != crc32(self.memory().rom()) {
+            return Err(SaveStateError::RomMismatch.into());
+        }
+        let [variant] = take_array(data)?;
+        if variant != self.variant() as u8 {
+            return Err(SaveStateError::VariantMismatch.into());
+        }
+        let [reg_a, reg_b, reg_c, reg_d, reg_e, reg_h, reg_l, flags] = take_array(data)?;
+        let state = CpuState {
+            reg_a,
+            reg_b,
+            reg_c,
+            reg_d,
+            reg_e,
+            reg_h,
+            reg_l,
+            flags,
+            sp: u16::from_le_bytes(take_array(data)?),
+            pc: u16::from_le_bytes(take_array(data)?),
+            interrupt_enabled: take_array::<1>(data)?[0] != 0,
+            halted: take_array::<1>(data)?[0] != 0,
+            variant: self.variant(),
+        };
+        let interrupt_delayed = take_array::<1>(data)?[0] != 0;
+        let [requested, instruction @ .., allow_nested_interrupt] = take_array::<5>(data)?;
+        let interrupt_request =
+            (requested != 0).then_some((instruction, allow_nested_interrupt != 0));
+        let pins = Pins8085::from_bytes(take_array(data)?);
+        let ram_len = u32::from_le_bytes(take_array(data)?) as usize;
+        if ram_len != self.memory().ram().len() {
+            return Err(SaveStateError::RamSizeMismatch.into());
+        }
+        let ram = take(data, ram_len)?;
+        self.memory_mut().ram_mut().copy_from_slice(ram);
+        self.set_state(&state);
+        self.interrupt_delayed = interrupt_delayed;
+        self.interrupt_request = interrupt_request;
+        self.pins = pins;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_state() {
+        let (mut cpu, _) = Cpu8080::new(vec![0x3e, 0x42], vec![0; 0x100], ());
+        cpu.step().unwrap();
+        cpu.memory_mut().ram_mut()[0x10] = 0xaa;
+        let saved = cpu.save_state();
+        assert_eq!(saved.len(), cpu.save_state_size());
+
+        let (mut restored, _) = Cpu8080::new(vec![0x3e, 0x42], vec![0; 0x100], ());
+        restored.load_state(&saved).unwrap();
+        assert_eq!(restored.state(), cpu.state());
+        assert_eq!(restored.get_ram(), cpu.get_ram());
+
+        let (mut other_rom, _) = Cpu8080::new(vec![0x3e, 0x43], vec![0; 0x100], ());
+        assert!(matches!(
+            other_rom.load_state(&saved),
+            Err(crate::EmulatorErrors::SaveState(
+                SaveStateError::RomMismatch
+            ))
+        ));
+        assert!(matches!(
+            restored.load_state(&saved[..saved.len() - 1]),
+            Err(crate::EmulatorErrors::SaveState(SaveStateError::Truncated))
+        ));
+    }
+
+    #[test]
+    fn pending_interrupt_is_saved() {
+        // LXI SP,0012H; EI; NOP, RST 1 is held until the NOP has run
+        let rom = vec![0x31, 0x12, 0x00, 0xfb, 0x00];
+        let (mut cpu, _) = Cpu8080::new(rom.clone(), vec![0; 0x10], ());
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.interrupt(&[0xcf], false).unwrap(), 0);
+        let saved = cpu.save_state();
+
+        let (mut restored, _) = Cpu8080::new(rom, vec![0; 0x10], ());
+        restored.load_state(&saved).unwrap();
+        assert!(restored.interrupt_delayed && restored.is_interrupt_pending());
+        assert_eq!(restored.step().unwrap().pc, 0x0008);
+    }
+
+    #[test]
+    fn pins_and_variant_are_saved() {
+        let (mut cpu, _) = Cpu8080::new(vec![0x00], vec![0; 0x10], ());
+        cpu.set_variant(crate::Variant::I8085);
+        cpu.set_interrupt_pin(crate::InterruptPin::Rst75, true)
+            .unwrap();
+        cpu.set_interrupt_pin(crate::InterruptPin::Rst55, true)
+            .unwrap();
+        cpu.set_sid(true).unwrap();
+        let saved = cpu.save_state();
+
+        let (mut restored, _) = Cpu8080::new(vec![0x00], vec![0; 0x10], ());
+        restored.set_variant(crate::Variant::I8085);
+        restored.load_state(&saved).unwrap();
+        assert_eq!(restored.pins, cpu.pins);
+        assert_eq!(restored.save_state(), saved);
+
+        let (mut i8080, _) = Cpu8080::new(vec![0x00], vec![0; 0x10], ());
+        assert!(matches!(
+            i8080.load_state(&saved),
+            Err(crate::EmulatorErrors::SaveState(
+                SaveStateError::VariantMismatch
+            ))
+        ));
+    }
+}
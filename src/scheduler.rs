@@ -0,0 +1,82 @@
+/// Handle of a scheduled event, used to cancel it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventId(pub(crate) u64);
+
+/// What happens when an event is due
+pub enum EventAction<P> {
+    /// Assert the INT line with `instruction`, e.g. RST 2 for a vblank,
+    /// see `Cpu8080::interrupt`
+    Interrupt {
+        instruction: [u8; 3],
+        allow_nested_interrupt: bool,
+    },
+    /// Let a device behind the IO ports update itself, e.g. a timer
+    Device(Box<dyn FnMut(&mut P)>),
+}
+
+pub(crate) struct Event<P> {
+    pub(crate) id: EventId,
+    /// Cycle count at which the event fires
+    pub(crate) at: u64,
+    pub(crate) period: Option<u64>,
+    pub(crate) action: EventAction<P>,
+}
+
+/// Events registered against the emulated cycle count,
+/// so they fire at the same emulated time on every run
+pub(crate) struct Scheduler<P> {
+    events: Vec<Event<P>>,
+    next_id: u64,
+}
+
+impl<P> Scheduler<P> {
+    pub(crate) fn new() -> Self {
+        Scheduler {
+            events: vec![],
+            next_id: 0,
+        }
+    }
+
+    pub(crate) fn add(&mut self, at: u64, period: Option<u64>, action: EventAction<P>) -> EventId {
+        let id = EventId(self.next_id);
+        self.next_id += 1;
+        self.events.push(Event {
+            id,
+            at,
+            period,
+            action,
+        });
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: EventId) -> bool {
+        let len = self.events.len();
+        self.events.retain(|event| event.id != id);
+        self.events.len() != len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Remove the earliest event due at `now`, ties
+    /// fire in the order they were registered
+    pub(crate) fn take_due(&mut self, now: u64) -> Option<Event<P>> {
+        let index = self
+            .events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| event.at <= now)
+            .min_by_key(|(_, event)| (event.at, event.id.0))?
+            .0;
+        Some(self.events.remove(index))
+    }
+
+    /// Put a periodic event back for its next period
+    pub(crate) fn reschedule(&mut self, mut event: Event<P>) {
+        if let Some(period) = event.period {
+            event.at += period;
+            self.events.push(event)
+        }
+    }
+}
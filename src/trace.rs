@@ -0,0 +1,204 @@
+use std::{cell::RefCell, collections::VecDeque, io::Write, rc::Rc};
+
+use crate::{disassemble, CpuState, Instruction, Result, Syntax, Variant};
+
+/// How a `TraceRecord` is rendered as a line of text
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// `0100: C3 AB 01  JMP 01ABH      A=00 BC=0000 DE=0000 HL=0000 SP=0000 F=-Z-P- CYC=0`
+    Listing,
+    /// `PC: 0100, AF: 0002, BC: 0000, DE: 0000, HL: 0000, SP: 0000, CYC: 0\t(C3 AB 01 00)`,
+    /// the debug output of superzazu/8080 and of many emulators
+    /// following it, to find the first divergence with `diff`
+    Registers,
+}
+
+/// The CPU right before executing an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub state: CpuState,
+    /// The bytes at `state.pc`, the instruction takes up to 3 of them
+    pub bytes: [u8; 4],
+    /// Clock cycles executed before the instruction
+    pub cycles: u64,
+}
+
+impl TraceRecord {
+    pub fn instruction(&self) -> Instruction {
+        disassemble(&self.bytes, Syntax::Intel, self.state.variant).expect("4 bytes are enough")
+    }
+
+    pub fn format(&self, format: TraceFormat) -> String {
+        let state = &self.state;
+        match format {
+            TraceFormat::Listing => {
+                let instruction = self.instruction();
+                let bytes = self.bytes[..instruction.len as usize]
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let codes = state.condition_codes();
+                let mut flags = vec![
+                    (codes.is_sign_set(), 'S'),
+                    (codes.is_zero_set(), 'Z'),
+                    (codes.is_aux_carry_set(), 'A'),
+                    (codes.is_parity_set(), 'P'),
+                    (codes.is_carry_set(), 'C'),
+                ];
+                // the 8085 adds signed underflow and overflow
+                if state.variant == Variant::I8085 {
+                    flags.extend([(codes.is_k_set(), 'K'), (codes.is_overflow_set(), 'V')]);
+                }
+                let flags = flags
+                    .iter()
+                    .map(|&(set, name)| if set { name } else { '-' })
+                    .collect::<String>();
+                format!(
+                    "{:04X}: {bytes:<8}  {:<14} A={:02X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} F={flags} CYC={}",
+                    state.pc,
+                    instruction.to_string(),
+                    state.reg_a,
+                    state.bc(),
+                    state.de(),
+                    state.hl(),
+                    state.sp,
+                    self.cycles
+                )
+            }
+            TraceFormat::Registers => {
+                let [b0, b1, b2, b3] = self.bytes;
+                format!(
+                    "PC: {:04X}, AF: {:04X}, BC: {:04X}, DE: {:04X}, HL: {:04X}, SP: {:04X}, CYC: {}\t({b0:02X} {b1:02X} {b2:02X} {b3:02X})",
+                    state.pc,
+                    u16::from_be_bytes([state.reg_a, state.flags]),
+                    state.bc(),
+                    state.de(),
+                    state.hl(),
+                    state.sp,
+                    self.cycles
+                )
+            }
+        }
+    }
+}
+
+/// Called before every instruction `Cpu8080::step` executes,
+/// see `Cpu8080::set_tracer`. An error stops the execution.
+pub trait Tracer {
+    fn trace(&mut self, record: &TraceRecord) -> Result<()>;
+}
+
+impl<F: FnMut(&TraceRecord)> Tracer for F {
+    fn trace(&mut self, record: &TraceRecord) -> Result<()> {
+        self(record);
+        Ok(())
+    }
+}
+
+/// Keeps the last `capacity` records, a clone shares the same
+/// records so it can be read while the CPU owns the tracer
+#[derive(Debug, Clone)]
+pub struct TraceBuffer {
+    records: Rc<RefCell<VecDeque<TraceRecord>>>,
+    capacity: usize,
+}
+
+impl TraceBuffer {
+    pub fn new(capacity: usize) -> Self {
+        TraceBuffer {
+            records: Rc::new(RefCell::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// From the oldest to the latest
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.borrow().iter().copied().collect()
+    }
+
+    pub fn clear(&self) {
+        self.records.borrow_mut().clear()
+    }
+}
+
+impl Tracer for TraceBuffer {
+    fn trace(&mut self, record: &TraceRecord) -> Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let mut records = self.records.borrow_mut();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(*record);
+        Ok(())
+    }
+}
+
+/// Writes one line per instruction
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Self {
+        TraceWriter { writer, format }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, record: &TraceRecord) -> Result<()> {
+        Ok(writeln!(self.writer, "{}", record.format(self.format))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Cpu8080};
+
+    #[test]
+    fn trace_lines() {
+        let rom = assemble(
+            "
+        LXI     SP,0100H
+        MVI     A,0FFH
+        ADI     1
+        JMP     0
+",
+        )
+        .unwrap();
+        let (mut cpu, _) = Cpu8080::new(rom, vec![0; 0x100], ());
+        let buffer = TraceBuffer::new(2);
+        cpu.set_tracer(buffer.clone());
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        let lines = buffer
+            .records()
+            .iter()
+            .map(|record| record.format(TraceFormat::Registers))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "PC: 0005, AF: FF02, BC: 0000, DE: 0000, HL: 0000, SP: 0100, CYC: 17\t(C6 01 C3 00)",
+                "PC: 0007, AF: 0057, BC: 0000, DE: 0000, HL: 0000, SP: 0100, CYC: 24\t(C3 00 00 00)",
+            ]
+        );
+        assert_eq!(
+            buffer.records()[1].format(TraceFormat::Listing),
+            "0007: C3 00 00  JMP 0000H      A=00 BC=0000 DE=0000 HL=0000 SP=0100 F=-ZAPC CYC=24"
+        );
+
+        // replaced by a closure, the buffer is left alone
+        cpu.set_tracer(|_: &TraceRecord| ());
+        cpu.step().unwrap();
+        assert_eq!(buffer.records()[1].state.pc, 0x0007);
+        assert!(cpu.remove_tracer().is_some());
+        assert!(cpu.remove_tracer().is_none());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{EmulatorErrors, MemoryOutOfBounds, Result};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 4 bytes per pixel: red, green, blue, alpha
+    Rgba8888,
+    /// 2 bytes per pixel in native endianness,
+    /// red in the 5 most significant bits
+    Rgb565,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+}
+
+/// How the monitor is mounted in the cabinet
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Upright,
+    Clockwise,
+    /// Space Invaders: each VRAM row is a screen column, from bottom to top
+    CounterClockwise,
+}
+
+/// Where the 1bpp picture lives in RAM: `rows` of `stride` bytes
+/// from `offset`, 8 pixels per byte with the least significant bit first
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoLayout {
+    pub offset: usize,
+    pub stride: usize,
+    pub rows: usize,
+    pub rotation: Rotation,
+}
+
+/// Lit pixels inside the rectangle take `color` (0xRRGGBB) instead
+/// of white, like the cellophane stripes glued on the monitor
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorBand {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub color: u32,
+}
+
+impl VideoLayout {
+    /// 224 rows of 32 bytes at 0x2400, i.e. 0x400 into the RAM
+    /// following the 8 KiB ROM, displayed as 224x256
+    pub const SPACE_INVADERS: VideoLayout = VideoLayout {
+        offset: 0x400,
+        stride: 32,
+        rows: 224,
+        rotation: Rotation::CounterClockwise,
+    };
+
+    /// Width and height of the picture once rotated
+    pub fn output_size(&self) -> (usize, usize) {
+        let (width, height) = (self.stride * 8, self.rows);
+        match self.rotation {
+            Rotation::Upright => (width, height),
+            Rotation::Clockwise | Rotation::CounterClockwise => (height, width),
+        }
+    }
+
+    pub fn buffer_size(&self, format: PixelFormat) -> usize {
+        let (width, height) = self.output_size();
+        width * height * format.bytes_per_pixel()
+    }
+
+    /// Is the pixel of the rotated picture at `x`, `y` lit
+    fn pixel(&self, vram: &[u8], x: usize, y: usize) -> bool {
+        let (row, column) = match self.rotation {
+            Rotation::Upright => (y, x),
+            Rotation::Clockwise => (self.rows - 1 - x, y),
+            Rotation::CounterClockwise => (x, self.stride * 8 - 1 - y),
+        };
+        vram[row * self.stride + column / 8] & 1 << (column % 8) != 0
+    }
+}
+
+/// The red band where the UFO flies and the green
+/// one over the bases and the remaining lives
+pub const SPACE_INVADERS_OVERLAY: [ColorBand; 3] = [
+    ColorBand {
+        x: 0,
+        y: 32,
+        width: 224,
+        height: 32,
+        color: 0xff0000,
+    },
+    ColorBand {
+        x: 0,
+        y: 184,
+        width: 224,
+        height: 56,
+        color: 0x00ff00,
+    },
+    ColorBand {
+        x: 16,
+        y: 240,
+        width: 118,
+        height: 16,
+        color: 0x00ff00,
+    },
+];
+
+const WHITE: u32 = 0xffffff;
+
+fn color_at(overlay: &[ColorBand], x: usize, y: usize) -> u32 {
+    overlay
+        .iter()
+        .find(|band| {
+            (band.x..band.x + band.width).contains(&x)
+                && (band.y..band.y + band.height).contains(&y)
+        })
+        .map_or(WHITE, |band| band.color)
+}
+
+fn encode(color: u32, format: PixelFormat, out: &mut [u8]) {
+    let [_, r, g, b] = color.to_be_bytes();
+    match format {
+        PixelFormat::Rgba8888 => out.copy_from_slice(&[r, g, b, 0xff]),
+        PixelFormat::Rgb565 => {
+            let pixel = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+            out.copy_from_slice(&pixel.to_ne_bytes())
+        }
+    }
+}
+
+/// Convert the 1bpp picture in `ram` into `out`, rotated, row by row
+/// from the top left, unlit pixels are black, lit ones white or the
+/// color of the first band of `overlay` containing them
+pub fn decode_frame(
+    ram: &[u8],
+    layout: &VideoLayout,
+    overlay: &[ColorBand],
+    format: PixelFormat,
+    out: &mut [u8],
+) -> Result<()> {
+    let vram = ram
+        .get(layout.offset..layout.offset + layout.stride * layout.rows)
+        .ok_or(MemoryOutOfBounds)?;
+    let out = out
+        .get_mut(..layout.buffer_size(format))
+        .ok_or(EmulatorErrors::InvalidArgument(
+            "output buffer is too small",
+        ))?;
+    let (width, _) = layout.output_size();
+    for (i, pixel) in out.chunks_exact_mut(format.bytes_per_pixel()).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let color = if layout.pixel(vram, x, y) {
+            color_at(overlay, x, y)
+        } else {
+            0
+        };
+        encode(color, format, pixel)
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn space_invaders_frame() {
+        let layout = VideoLayout {
+            offset: 0,
+            ..VideoLayout::SPACE_INVADERS
+        };
+        let mut vram = vec![0; 32 * 224];
+        // bottom left pixel, and the top of the second column
+        vram[0] = 0x01;
+        vram[32 + 31] = 0x80;
+        // inside the red band
+        vram[100 * 32 + (255 - 40) / 8] = 1 << ((255 - 40) % 8);
+        let mut out = vec![0; layout.buffer_size(PixelFormat::Rgba8888)];
+        decode_frame(
+            &vram,
+            &layout,
+            &SPACE_INVADERS_OVERLAY,
+            PixelFormat::Rgba8888,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(layout.output_size(), (224, 256));
+        let pixel = |x: usize, y: usize| &out[(y * 224 + x) * 4..][..4];
+        assert_eq!(pixel(0, 255), [0xff; 4]);
+        assert_eq!(pixel(1, 0), [0xff; 4]);
+        assert_eq!(pixel(100, 40), [0xff, 0, 0, 0xff]);
+        assert_eq!(pixel(0, 0), [0, 0, 0, 0xff]);
+        assert_eq!(out.chunks(4).filter(|p| p[0] != 0 || p[1] != 0).count(), 3);
+
+        let mut out = vec![0; layout.buffer_size(PixelFormat::Rgb565)];
+        decode_frame(
+            &vram,
+            &layout,
+            &SPACE_INVADERS_OVERLAY,
+            PixelFormat::Rgb565,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out[(100 + 40 * 224) * 2..][..2], 0xf800u16.to_ne_bytes());
+        assert!(decode_frame(&vram[1..], &layout, &[], PixelFormat::Rgb565, &mut out).is_err());
+        assert!(decode_frame(&vram, &layout, &[], PixelFormat::Rgba8888, &mut out).is_err());
+    }
+
+    #[test]
+    fn rotations() {
+        // 2 rows of 8 pixels, only the first pixel of the second row lit
+        let vram = [0x00, 0x01];
+        let lit = |rotation| {
+            let layout = VideoLayout {
+                offset: 0,
+                stride: 1,
+                rows: 2,
+                rotation,
+            };
+            let mut out = vec![0; layout.buffer_size(PixelFormat::Rgba8888)];
+            decode_frame(&vram, &layout, &[], PixelFormat::Rgba8888, &mut out).unwrap();
+            let (width, _) = layout.output_size();
+            let i = out.chunks(4).position(|p| p[0] == 0xff).unwrap();
+            (i % width, i / width)
+        };
+        assert_eq!(lit(Rotation::Upright), (0, 1));
+        assert_eq!(lit(Rotation::Clockwise), (0, 0));
+        assert_eq!(lit(Rotation::CounterClockwise), (1, 7));
+    }
+}
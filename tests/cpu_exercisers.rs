@@ -0,0 +1,95 @@
+//! The 8080 test programs, run under the CP/M personality
+//! of the emulator, see tests/roms/README.md
+
+use std::{cell::RefCell, env, fs, io, path::Path, rc::Rc};
+
+use i8080emulator::{load_com, Bdos, Cpu8080};
+
+/// The console output, shared between the BDOS and the test
+#[derive(Clone, Default)]
+struct Console(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `program` until it warm boots, fails if it takes more than `max_cycles`
+fn run_com(program: &[u8], max_cycles: u64) -> String {
+    let (mut cpu, _) = Cpu8080::with_memory(load_com(program).unwrap(), ());
+    let console = Console::default();
+    cpu.enable_cpm(Bdos::new(console.clone()));
+    while !cpu.has_exited() {
+        cpu.run_for_cycles(1_000_000).unwrap();
+        assert!(
+            cpu.cycles() < max_cycles,
+            "no warm boot after {} cycles, output so far:\n{}",
+            cpu.cycles(),
+            String::from_utf8_lossy(&console.0.borrow())
+        );
+    }
+    let output = String::from_utf8_lossy(&console.0.borrow()).into_owned();
+    output
+}
+
+/// `None` when the program has not been fetched, see tests/roms/README.md
+fn exerciser(name: &str) -> Option<Vec<u8>> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/roms")
+        .join(name);
+    match fs::read(&path) {
+        Ok(program) => Some(program),
+        Err(err) if env::var_os("I8080_REQUIRE_EXERCISERS").is_none() => {
+            eprintln!("{}: {err}, skipped", path.display());
+            None
+        }
+        Err(err) => panic!("{}: {err}", path.display()),
+    }
+}
+
+#[test]
+fn preliminary() {
+    let Some(program) = exerciser("8080PRE.COM") else {
+        return;
+    };
+    let output = run_com(&program, 10_000_000);
+    assert!(
+        output.contains("8080 Preliminary tests complete"),
+        "{output}"
+    );
+}
+
+#[test]
+fn microcosm_diagnostic() {
+    let Some(program) = exerciser("TST8080.COM") else {
+        return;
+    };
+    let output = run_com(&program, 10_000_000);
+    assert!(output.contains("CPU IS OPERATIONAL"), "{output}");
+}
+
+#[test]
+fn supersoft_cpu_test() {
+    let Some(program) = exerciser("CPUTEST.COM") else {
+        return;
+    };
+    let output = run_com(&program, 1_000_000_000);
+    assert!(output.contains("CPU TESTS OK"), "{output}");
+}
+
+#[test]
+#[ignore = "billions of cycles, run with --release -- --include-ignored"]
+fn instruction_exerciser() {
+    let Some(program) = exerciser("8080EXM.COM") else {
+        return;
+    };
+    let output = run_com(&program, 100_000_000_000);
+    // every group of instructions prints OK or ERROR with the CRCs
+    assert!(output.contains("Tests complete"), "{output}");
+    assert!(!output.contains("ERROR"), "{output}");
+}
@@ -0,0 +1,7 @@
+#!/bin/sh
+# Download the CP/M exercisers run by tests/cpu_exercisers.rs next to this script
+set -e
+cd "$(dirname "$0")"
+for name in 8080PRE.COM TST8080.COM CPUTEST.COM 8080EXM.COM; do
+    curl -fsSLo "$name" "https://raw.githubusercontent.com/superzazu/8080/master/cpu_tests/$name"
+done